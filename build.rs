@@ -15,6 +15,7 @@ fn main() {
         #[cfg(not(windows))]
         pkg_config::Config::new()
             .statik(statik)
+            .atleast_version(min_version())
             .probe("liblzma")
             .expect("Could not find liblzma using pkg-config");
     }
@@ -23,3 +24,12 @@ fn main() {
         println!("cargo:rustc-link-lib=static=lzma");
     }
 }
+
+/// 5.2 is needed for lzma_stream_encoder_mt and lzma_cputhreads, and some features need symbols
+/// that were added in 5.4
+#[cfg(not(windows))]
+fn min_version() -> &'static str {
+    let needs_5_4 = ["MICROLZMA", "FILTER_STRINGS", "LZIP"].iter().any(|feature| env::var(format!("CARGO_FEATURE_{}", feature)).is_ok());
+
+    if needs_5_4 { "5.4.0" } else { "5.2.0" }
+}
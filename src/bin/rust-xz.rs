@@ -0,0 +1,467 @@
+//! `rust-xz` is a small xz-compatible command line tool built on `LzmaReader` and `LzmaWriter`.
+//!
+//! It's only built when the `cli` feature is enabled:
+//!
+//! ```text
+//! cargo build --release --features cli
+//! ```
+//!
+//! The supported options are a subset of xz's; run `rust-xz --help` for the list.
+
+extern crate lzma;
+
+use lzma::{LzmaReader, LzmaWriter, EncoderOptions, DecoderOptions, Format, EXTREME_PRESET};
+use lzma::check::Check;
+use lzma::filter::FilterChain;
+use lzma::index::Index;
+use std::convert::TryFrom;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::process;
+
+
+const USAGE: &str = "Usage: rust-xz [OPTION]... [FILE]...
+Compress or decompress FILEs in the .xz format.
+
+Operation mode:
+  -z, --compress      force compression
+  -d, --decompress    force decompression
+  -t, --test          test compressed file integrity
+  -l, --list          list information about .xz files
+
+Operation modifiers:
+  -k, --keep          keep (don't delete) input files
+  -f, --force         overwrite existing output files
+  -c, --stdout        write to standard output and don't delete input files
+
+Basic file format and compression options:
+  -F, --format=FMT    file format to encode or decode; possible values are
+                      'auto' (default), 'xz', 'lzma', 'lzip', and 'raw'
+  -C, --check=CHECK   integrity check type: 'none' (use with caution),
+                      'crc32', 'crc64' (default), or 'sha256'
+  -0 ... -9           compression preset; default is 6
+  -e, --extreme       try to improve compression ratio by using more CPU time
+  -T, --threads=NUM   use at most NUM threads when compressing; 0 uses as many
+                      threads as there are processor cores
+  -M, --memlimit=LIMIT
+                      set memory usage limit for decompression
+
+  -h, --help          display this help and exit
+  -V, --version       display the version number and exit
+
+With no FILE, or when FILE is -, read standard input.";
+
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+	Compress,
+	Decompress,
+	Test,
+	List,
+}
+
+struct Args {
+	mode: Mode,
+	keep: bool,
+	force: bool,
+	stdout: bool,
+	preset: u32,
+	extreme: bool,
+	threads: u32,
+	check: Check,
+	memlimit: u64,
+	// None means auto-detect when decompressing, and .xz when compressing
+	format: Option<Format>,
+	files: Vec<String>,
+}
+
+type CliResult<T> = Result<T, Box<dyn Error>>;
+
+
+fn main() {
+	let args = match parse_args(env::args().skip(1).collect()) {
+		Ok(Some(args)) => args,
+		Ok(None) => return,
+		Err(err) => {
+			eprintln!("rust-xz: {}", err);
+			eprintln!("Try 'rust-xz --help' for more information.");
+			process::exit(1);
+		},
+	};
+
+	let files = if args.files.is_empty() {
+		vec!["-".to_string()]
+	} else {
+		args.files.clone()
+	};
+	let mut failed = false;
+
+	if args.mode == Mode::List {
+		println!("Strms  Blocks   Compressed Uncompressed  Ratio  Check   Filename");
+	}
+
+	for file in &files {
+		if let Err(err) = process_file(&args, file) {
+			eprintln!("rust-xz: {}: {}", if file == "-" { "(stdin)" } else { file }, err);
+			failed = true;
+		}
+	}
+
+	if failed {
+		process::exit(1);
+	}
+}
+
+
+/// Returns None if the program should exit successfully without doing anything (e.g. --help)
+fn parse_args(raw: Vec<String>) -> CliResult<Option<Args>> {
+	let mut args = Args {
+		mode: Mode::Compress,
+		keep: false,
+		force: false,
+		stdout: false,
+		preset: 6,
+		extreme: false,
+		threads: 1,
+		check: Check::Crc64,
+		memlimit: u64::MAX,
+		format: None,
+		files: Vec::new(),
+	};
+	let mut raw = raw.into_iter();
+
+	while let Some(arg) = raw.next() {
+		if arg == "--" {
+			args.files.extend(raw.by_ref());
+			break;
+		} else if let Some(long) = arg.strip_prefix("--") {
+			let (name, value) = match long.find('=') {
+				Some(i) => (&long[..i], Some(long[(i + 1)..].to_string())),
+				None => (long, None),
+			};
+			let mut value = || value.clone().or_else(|| raw.next()).ok_or_else(|| format!("option '--{}' requires an argument", name));
+
+			match name {
+				"compress" => args.mode = Mode::Compress,
+				"decompress" | "uncompress" => args.mode = Mode::Decompress,
+				"test" => args.mode = Mode::Test,
+				"list" => args.mode = Mode::List,
+				"keep" => args.keep = true,
+				"force" => args.force = true,
+				"stdout" | "to-stdout" => args.stdout = true,
+				"extreme" => args.extreme = true,
+				"format" => args.format = parse_format(&value()?)?,
+				"check" => args.check = parse_check(&value()?)?,
+				"threads" => args.threads = parse_threads(&value()?)?,
+				"memlimit" | "memory" => args.memlimit = parse_memlimit(&value()?)?,
+				"help" => {
+					println!("{}", USAGE);
+					return Ok(None);
+				},
+				"version" => {
+					print_version();
+					return Ok(None);
+				},
+				_ => return Err(format!("unrecognized option '--{}'", name).into()),
+			}
+		} else if arg.len() > 1 && arg.starts_with('-') {
+			let flags: Vec<char> = arg[1..].chars().collect();
+			let mut i = 0;
+
+			while i < flags.len() {
+				let flag = flags[i];
+				i += 1;
+
+				// Options taking a value use the rest of this argument, or the next argument
+				let mut value = || -> CliResult<String> {
+					let rest: String = flags[i..].iter().collect();
+					i = flags.len();
+
+					if rest.is_empty() {
+						raw.next().ok_or_else(|| format!("option requires an argument -- '{}'", flag).into())
+					} else {
+						Ok(rest)
+					}
+				};
+
+				match flag {
+					'z' => args.mode = Mode::Compress,
+					'd' => args.mode = Mode::Decompress,
+					't' => args.mode = Mode::Test,
+					'l' => args.mode = Mode::List,
+					'k' => args.keep = true,
+					'f' => args.force = true,
+					'c' => args.stdout = true,
+					'e' => args.extreme = true,
+					'0'..='9' => args.preset = flag as u32 - '0' as u32,
+					'F' => args.format = parse_format(&value()?)?,
+					'C' => args.check = parse_check(&value()?)?,
+					'T' => args.threads = parse_threads(&value()?)?,
+					'M' => args.memlimit = parse_memlimit(&value()?)?,
+					'h' => {
+						println!("{}", USAGE);
+						return Ok(None);
+					},
+					'V' => {
+						print_version();
+						return Ok(None);
+					},
+					_ => return Err(format!("invalid option -- '{}'", flag).into()),
+				}
+			}
+		} else {
+			args.files.push(arg);
+		}
+	}
+
+	if args.mode == Mode::Compress && args.format == Some(Format::Lzip) {
+		return Err("--format=lzip is supported only when decompressing".into());
+	}
+
+	Ok(Some(args))
+}
+
+fn print_version() {
+	println!("rust-xz {}", env!("CARGO_PKG_VERSION"));
+	println!("liblzma {}", lzma::capabilities().version_string());
+}
+
+fn parse_format(s: &str) -> CliResult<Option<Format>> {
+	match s {
+		"auto" => Ok(None),
+		"xz" => Ok(Some(Format::Xz)),
+		"lzma" | "alone" => Ok(Some(Format::Lzma)),
+		"lzip" => Ok(Some(Format::Lzip)),
+		"raw" => Ok(Some(Format::Raw)),
+		_ => Err(format!("{}: unknown file format type", s).into()),
+	}
+}
+
+fn parse_check(s: &str) -> CliResult<Check> {
+	match s {
+		"none" => Ok(Check::None),
+		"crc32" => Ok(Check::Crc32),
+		"crc64" => Ok(Check::Crc64),
+		"sha256" => Ok(Check::Sha256),
+		_ => Err(format!("{}: unsupported integrity check type", s).into()),
+	}
+}
+
+/// Parses a number with an optional KiB/MiB/GiB suffix, like xz does
+fn parse_number(s: &str) -> CliResult<u64> {
+	let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+	let (digits, suffix) = s.split_at(split);
+	let multiplier: u64 = match suffix {
+		"" => 1,
+		"k" | "K" | "KB" | "KiB" => 1 << 10,
+		"m" | "M" | "MB" | "MiB" => 1 << 20,
+		"g" | "G" | "GB" | "GiB" => 1 << 30,
+		_ => return Err(format!("{}: invalid multiplier suffix", s).into()),
+	};
+	let number: u64 = digits.parse().map_err(|_| format!("{}: invalid number", s))?;
+
+	number.checked_mul(multiplier).ok_or_else(|| format!("{}: value is too large", s).into())
+}
+
+fn parse_threads(s: &str) -> CliResult<u32> {
+	u32::try_from(parse_number(s)?).map_err(|_| format!("{}: value is too large", s).into())
+}
+
+fn parse_memlimit(s: &str) -> CliResult<u64> {
+	match s {
+		// As in xz, 0 means no limit
+		"max" | "0" => Ok(u64::MAX),
+		_ => parse_number(s),
+	}
+}
+
+
+fn encoder_options(args: &Args) -> EncoderOptions {
+	let preset = if args.extreme { args.preset | EXTREME_PRESET } else { args.preset };
+
+	EncoderOptions::new(preset)
+		.check(args.check)
+		.format(args.format.unwrap_or(Format::Xz))
+		.threads(args.threads)
+}
+
+fn decoder_options(args: &Args) -> CliResult<DecoderOptions> {
+	let mut options = DecoderOptions::new().memlimit(args.memlimit).concatenated(true);
+
+	if let Some(format) = args.format {
+		options = options.format(format);
+	}
+
+	// Raw streams don't record their settings, so the preset has to match the one used to compress
+	if args.format == Some(Format::Raw) {
+		let preset = if args.extreme { args.preset | EXTREME_PRESET } else { args.preset };
+		options = options.filters(FilterChain::preset(preset)?);
+	}
+
+	Ok(options)
+}
+
+
+fn process_file(args: &Args, name: &str) -> CliResult<()> {
+	match args.mode {
+		Mode::List => return list(name),
+		Mode::Test => {
+			let mut reader = LzmaReader::new_decompressor_with_options(open_input(name)?, &decoder_options(args)?)?;
+			io::copy(&mut reader, &mut io::sink())?;
+			return Ok(());
+		},
+		Mode::Compress | Mode::Decompress => (),
+	}
+
+	if name == "-" || args.stdout {
+		let stdout = io::stdout();
+		let mut stdout = stdout.lock();
+
+		code(args, open_input(name)?, &mut stdout)?;
+		return stdout.flush().map_err(From::from);
+	}
+
+	let output_name = output_name(args, name)?;
+
+	let input = File::open(name)?;
+	let permissions = input.metadata()?.permissions();
+	let mut open_options = OpenOptions::new();
+	open_options.write(true);
+
+	// Checking whether the file exists first would race with whoever else creates it
+	if args.force {
+		open_options.create(true).truncate(true);
+	} else {
+		open_options.create_new(true);
+	}
+
+	let output = match open_options.open(&output_name) {
+		Ok(output) => output,
+		Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => return Err(format!("{}: File exists", output_name).into()),
+		Err(err) => return Err(err.into()),
+	};
+	let result = code(args, input, output).and_then(|output| {
+		output.sync_all()?;
+		fs::set_permissions(&output_name, permissions)?;
+		Ok(())
+	});
+
+	match result {
+		Ok(()) if !args.keep => fs::remove_file(name).map_err(From::from),
+		Ok(()) => Ok(()),
+		Err(err) => {
+			let _ = fs::remove_file(&output_name);
+			Err(err)
+		},
+	}
+}
+
+/// Compresses or decompresses input into output
+fn code<R: Read, W: Write>(args: &Args, mut input: R, output: W) -> CliResult<W> {
+	if args.mode == Mode::Compress {
+		let mut writer = LzmaWriter::new_compressor_with_options(output, &encoder_options(args))?;
+
+		match io::copy(&mut input, &mut writer) {
+			Ok(_) => Ok(writer.finish()?),
+			// The output is abandoned, so there's no point finishing it
+			Err(err) => {
+				writer.into_inner();
+				Err(err.into())
+			},
+		}
+	} else {
+		let mut reader = LzmaReader::new_decompressor_with_options(input, &decoder_options(args)?)?;
+		let mut output = output;
+		io::copy(&mut reader, &mut output)?;
+		Ok(output)
+	}
+}
+
+fn open_input(name: &str) -> CliResult<Box<dyn Read>> {
+	if name == "-" {
+		Ok(Box::new(io::stdin()))
+	} else {
+		Ok(Box::new(File::open(name)?))
+	}
+}
+
+fn output_name(args: &Args, name: &str) -> CliResult<String> {
+	if args.mode == Mode::Compress {
+		let suffix = match args.format {
+			None | Some(Format::Xz) => ".xz",
+			Some(Format::Lzma) => ".lzma",
+			Some(Format::Lzip) => return Err("--format=lzip is supported only when decompressing".into()),
+			Some(Format::Raw) => return Err("Compressing raw streams to a file requires --stdout".into()),
+		};
+
+		if name.ends_with(suffix) {
+			return Err(format!("File already has '{}' suffix, skipping", suffix).into());
+		}
+
+		return Ok(format!("{}{}", name, suffix));
+	}
+
+	let suffixes: &[(&str, &str)] = match args.format {
+		None => &[(".xz", ""), (".txz", ".tar"), (".lzma", ""), (".tlz", ".tar"), (".lz", "")],
+		Some(Format::Xz) => &[(".xz", ""), (".txz", ".tar")],
+		Some(Format::Lzma) => &[(".lzma", ""), (".tlz", ".tar")],
+		Some(Format::Lzip) => &[(".lz", "")],
+		Some(Format::Raw) => return Err("Decompressing raw streams to a file requires --stdout".into()),
+	};
+
+	for &(suffix, replacement) in suffixes {
+		if let Some(stem) = name.strip_suffix(suffix) {
+			if !stem.is_empty() {
+				return Ok(format!("{}{}", stem, replacement));
+			}
+		}
+	}
+
+	Err("Filename has an unknown suffix, skipping".into())
+}
+
+
+fn list(name: &str) -> CliResult<()> {
+	if name == "-" {
+		return Err("--list does not support reading from standard input".into());
+	}
+
+	let index = Index::from_reader(&mut File::open(name)?)?;
+	let checks: Vec<&str> = index.checks().iter().map(|check| match *check {
+		Check::None => "None",
+		Check::Crc32 => "CRC32",
+		Check::Crc64 => "CRC64",
+		Check::Sha256 => "SHA-256",
+	}).collect();
+	let ratio = if index.uncompressed_size() == 0 {
+		"---".to_string()
+	} else {
+		format!("{:.3}", index.file_size() as f64 / index.uncompressed_size() as f64)
+	};
+
+	println!("{:>5} {:>7} {:>12} {:>12} {:>6}  {:<7} {}",
+		index.stream_count(), index.block_count(), human_size(index.file_size()),
+		human_size(index.uncompressed_size()), ratio, checks.join(","), name);
+
+	Ok(())
+}
+
+fn human_size(size: u64) -> String {
+	const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+	if size < 1024 {
+		return format!("{} B", size);
+	}
+
+	let mut value = size as f64 / 1024.0;
+	let mut unit = 0;
+
+	while value >= 1024.0 && unit < UNITS.len() - 1 {
+		value /= 1024.0;
+		unit += 1;
+	}
+
+	format!("{:.1} {}", value, UNITS[unit])
+}
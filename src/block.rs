@@ -0,0 +1,493 @@
+//! This module implements a low-level interface to individual XZ blocks.
+//!
+//! An XZ stream is made of a stream header, any number of blocks, an index and a stream footer.
+//! `Block` describes a single block (its filters, integrity check and sizes) and can encode and
+//! decode block headers.  `BlockEncoder` and `BlockDecoder` compress and decompress the block's
+//! data, which immediately follows the block header.
+//!
+//! This is only useful when building your own container around XZ blocks; `LzmaReader` and
+//! `LzmaWriter` already handle complete XZ streams.  `blocks` goes through the blocks of an
+//! existing `.xz` file one by one, which is handy for processing them in parallel.
+//!
+//!
+//! # Examples
+//!
+//! Compress some data into a block whose header records the block's sizes:
+//!
+//! ```
+//! use lzma::block::{Block, BlockEncoder, BlockDecoder};
+//! use lzma::check::Check;
+//! use lzma::filter::FilterChain;
+//! use std::io::prelude::*;
+//!
+//! let block = Block::new(FilterChain::preset(6).unwrap(), Check::Crc64);
+//! let mut encoder = BlockEncoder::new(Vec::new(), &block).unwrap();
+//! encoder.write_all(b"All those moments will be lost in time").unwrap();
+//! let (data, block) = encoder.finish().unwrap();
+//!
+//! // The sizes are now known, so they get recorded in the header
+//! let mut output = block.encode_header().unwrap();
+//! output.extend_from_slice(&data);
+//!
+//! // Reading it back
+//! let block = Block::decode_header(&output, Check::Crc64).unwrap();
+//! let header_size = block.header_size().unwrap();
+//! let mut decoder = BlockDecoder::new(&output[header_size..], &block).unwrap();
+//! let mut s = String::new();
+//! decoder.read_to_string(&mut s).unwrap();
+//!
+//! assert_eq!(s, "All those moments will be lost in time");
+//! ```
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ptr;
+use std::vec;
+use lzma_sys::*;
+use error::{LzmaError, LzmaLibResult};
+use check::Check;
+use filter::{self, FilterChain, RawFilters};
+use lzma_stream_wrapper::LzmaStreamWrapper;
+use reader::LzmaReader;
+use writer::LzmaWriter;
+use capabilities;
+use xz::{FileInfo, IndexRecord};
+
+
+const DEFAULT_BUF_SIZE: usize = 4 * 1024;
+
+
+/// Describes a single XZ block
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+	check: Check,
+	filters: FilterChain,
+	header_size: Option<u32>,
+	compressed_size: Option<u64>,
+	uncompressed_size: Option<u64>,
+}
+
+
+impl Block {
+	/// A block using the given filters and integrity check, with unknown sizes
+	pub fn new(filters: FilterChain, check: Check) -> Block {
+		Block {
+			check,
+			filters,
+			header_size: None,
+			compressed_size: None,
+			uncompressed_size: None,
+		}
+	}
+
+	/// Decodes a block header.
+	///
+	/// `header` must start with the block header; anything after the header is ignored.
+	/// `check` comes from the stream header, since the block header doesn't record it.
+	pub fn decode_header(header: &[u8], check: Check) -> Result<Block, LzmaError> {
+		let header_size = match header.first() {
+			None => return Err(LzmaError::Buf),
+			// A zero byte is the Index Indicator, not a block header
+			Some(&0) => return Err(LzmaError::Data),
+			Some(&b) => Block::header_size_from_first_byte(b),
+		};
+
+		if header.len() < header_size {
+			return Err(LzmaError::Buf);
+		}
+
+		let mut filters: Vec<lzma_filter> = (0..=LZMA_FILTERS_MAX).map(|_| lzma_filter {
+			id: LZMA_VLI_UNKNOWN,
+			options: ptr::null_mut(),
+		}).collect();
+		let mut raw = lzma_block::new();
+		raw.version = 1;
+		raw.header_size = header_size as u32;
+		raw.check = check.to_raw();
+		raw.filters = filters.as_mut_ptr();
+
+		let allocator = filter::rust_allocator();
+
+		unsafe {
+			// On failure liblzma frees the filters itself
+			LzmaLibResult::from(lzma_block_header_decode(&mut raw, &allocator, header.as_ptr())).map(|_| ())?;
+
+			let chain = FilterChain::from_raw(filters.as_ptr(), true);
+			filter::free_raw_filters(&mut filters);
+
+			Ok(Block {
+				check,
+				filters: chain?,
+				header_size: Some(raw.header_size),
+				compressed_size: vli_to_option(raw.compressed_size),
+				uncompressed_size: vli_to_option(raw.uncompressed_size),
+			})
+		}
+	}
+
+	/// Given the first byte of a block header, returns the size of the whole header
+	pub fn header_size_from_first_byte(b: u8) -> usize {
+		(b as usize + 1) * 4
+	}
+
+	/// Encodes the block header.  The compressed and uncompressed sizes are recorded in the header
+	/// if they are known.
+	pub fn encode_header(&self) -> Result<Vec<u8>, LzmaError> {
+		let raw = self.to_raw()?;
+		let mut header = vec![0u8; raw.block.header_size as usize];
+
+		unsafe {
+			LzmaLibResult::from(lzma_block_header_encode(&*raw.block, header.as_mut_ptr())).map(|_| ())?;
+		}
+
+		Ok(header)
+	}
+
+	/// The size of the encoded block header
+	pub fn header_size(&self) -> Result<usize, LzmaError> {
+		Ok(self.to_raw()?.block.header_size as usize)
+	}
+
+	pub fn check(&self) -> Check {
+		self.check
+	}
+
+	pub fn filters(&self) -> &FilterChain {
+		&self.filters
+	}
+
+	pub fn compressed_size(&self) -> Option<u64> {
+		self.compressed_size
+	}
+
+	pub fn uncompressed_size(&self) -> Option<u64> {
+		self.uncompressed_size
+	}
+
+	/// Sets the size of the compressed data, excluding the header, padding and check
+	pub fn set_compressed_size(&mut self, size: Option<u64>) {
+		self.compressed_size = size;
+	}
+
+	pub fn set_uncompressed_size(&mut self, size: Option<u64>) {
+		self.uncompressed_size = size;
+	}
+
+	/// Sets the compressed size from the Unpadded Size stored in an XZ index.  The header size
+	/// and check must already be correct.
+	pub fn set_unpadded_size(&mut self, unpadded_size: u64) -> Result<(), LzmaError> {
+		let mut raw = self.to_raw()?;
+
+		unsafe {
+			LzmaLibResult::from(lzma_block_compressed_size(&mut *raw.block, unpadded_size)).map(|_| ())?;
+		}
+
+		self.compressed_size = vli_to_option(raw.block.compressed_size);
+		Ok(())
+	}
+
+	/// Size of the header, compressed data and check, as stored in an XZ index.
+	/// Returns None if the compressed size isn't known.
+	pub fn unpadded_size(&self) -> Option<u64> {
+		let raw = self.to_raw().ok()?;
+
+		match unsafe { lzma_block_unpadded_size(&*raw.block) } {
+			0 | LZMA_VLI_UNKNOWN => None,
+			size => Some(size),
+		}
+	}
+
+	/// Total size of the block, including padding.
+	/// Returns None if the compressed size isn't known.
+	pub fn total_size(&self) -> Option<u64> {
+		let raw = self.to_raw().ok()?;
+
+		match unsafe { lzma_block_total_size(&*raw.block) } {
+			0 | LZMA_VLI_UNKNOWN => None,
+			size => Some(size),
+		}
+	}
+
+	pub(crate) fn to_raw(&self) -> Result<RawBlock, LzmaError> {
+		let mut raw = RawBlock {
+			block: Box::new(lzma_block::new()),
+			filters: self.filters.to_raw()?,
+		};
+
+		raw.block.version = 1;
+		raw.block.check = self.check.to_raw();
+		raw.block.compressed_size = self.compressed_size.unwrap_or(LZMA_VLI_UNKNOWN);
+		raw.block.uncompressed_size = self.uncompressed_size.unwrap_or(LZMA_VLI_UNKNOWN);
+		raw.block.filters = raw.filters.as_mut_ptr();
+
+		match self.header_size {
+			Some(header_size) => raw.block.header_size = header_size,
+			None => unsafe {
+				LzmaLibResult::from(lzma_block_header_size(&mut *raw.block)).map(|_| ())?;
+			},
+		}
+
+		Ok(raw)
+	}
+
+	/// Copies the sizes liblzma recorded after coding a block
+	pub(crate) fn update_from_raw(&mut self, raw: &RawBlock) {
+		self.compressed_size = vli_to_option(raw.block.compressed_size);
+		self.uncompressed_size = vli_to_option(raw.block.uncompressed_size);
+	}
+}
+
+
+/// An `lzma_block` along with the filters it points to
+pub(crate) struct RawBlock {
+	block: Box<lzma_block>,
+	filters: RawFilters,
+}
+
+impl RawBlock {
+	pub(crate) fn as_mut_ptr(&mut self) -> *mut lzma_block {
+		&mut *self.block
+	}
+}
+
+
+fn vli_to_option(vli: lzma_vli) -> Option<u64> {
+	if vli == LZMA_VLI_UNKNOWN {
+		None
+	} else {
+		Some(vli)
+	}
+}
+
+
+/// Compresses the data of a single block.  Only the compressed data (plus block padding and
+/// check) is written; the block header is not.
+pub struct BlockEncoder<W: Write> {
+	writer: LzmaWriter<W>,
+	block: Block,
+}
+
+impl<W: Write> BlockEncoder<W> {
+	pub fn new(inner: W, block: &Block) -> Result<BlockEncoder<W>, LzmaError> {
+		capabilities::require_check(block.check)?;
+		capabilities::require_encoder(block.filters.filters())?;
+
+		let mut stream = LzmaStreamWrapper::new();
+		stream.block_encoder(block.to_raw()?)?;
+
+		Ok(BlockEncoder {
+			writer: LzmaWriter::from_stream(DEFAULT_BUF_SIZE, inner, stream),
+			block: block.clone(),
+		})
+	}
+
+	/// Finishes the block, returning the inner writer and the block with its sizes filled in.
+	///
+	/// If sizes were given in the block passed to `new`, they are verified.
+	pub fn finish(mut self) -> Result<(W, Block), LzmaError> {
+		self.writer.try_finish()?;
+
+		// liblzma overwrites the sizes without looking at them, so compare them here
+		let expected = self.block.clone();
+
+		if let Some(raw) = self.writer.stream().block() {
+			self.block.update_from_raw(raw);
+		}
+
+		let mismatch = |expected: Option<u64>, actual: Option<u64>| expected.is_some() && expected != actual;
+
+		if mismatch(expected.compressed_size, self.block.compressed_size) || mismatch(expected.uncompressed_size, self.block.uncompressed_size) {
+			return Err(LzmaError::Data);
+		}
+
+		Ok((self.writer.into_inner(), self.block))
+	}
+}
+
+impl<W: Write> Write for BlockEncoder<W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.writer.write(buf)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.writer.flush()
+	}
+}
+
+
+/// Decompresses the data of a single block, which starts immediately after the block header.
+///
+/// Sizes recorded in the block are verified, as is the block's integrity check.  `BlockDecoder`
+/// reads ahead, so the inner reader may be advanced past the end of the block.
+pub struct BlockDecoder<R: Read> {
+	reader: LzmaReader<R>,
+	block: Block,
+}
+
+impl<R: Read> BlockDecoder<R> {
+	pub fn new(inner: R, block: &Block) -> Result<BlockDecoder<R>, LzmaError> {
+		capabilities::require_decoder(block.filters.filters())?;
+
+		let mut stream = LzmaStreamWrapper::new();
+		stream.block_decoder(block.to_raw()?)?;
+
+		Ok(BlockDecoder {
+			reader: LzmaReader::from_stream(DEFAULT_BUF_SIZE, inner, stream),
+			block: block.clone(),
+		})
+	}
+
+	/// The block being decoded.  Once the block has been read to the end, its sizes are known.
+	pub fn block(&self) -> Block {
+		let mut block = self.block.clone();
+
+		if let Some(raw) = self.reader.stream().block() {
+			block.update_from_raw(raw);
+		}
+
+		block
+	}
+
+	pub fn into_inner(self) -> R {
+		self.reader.into_inner()
+	}
+}
+
+impl<R: Read> Read for BlockDecoder<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.reader.read(buf)
+	}
+}
+
+
+/// A block decoded by `blocks`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodedBlock {
+	/// Where the block's data starts in the decompressed output
+	pub uncompressed_offset: u64,
+	/// Where the block, starting with its header, is in the file
+	pub compressed_offset: u64,
+	/// The block header, with the sizes filled in from the index
+	pub block: Block,
+	pub data: Vec<u8>,
+}
+
+
+/// Decompresses the `.xz` file in `reader` one block at a time.
+///
+/// The file's indexes are read first to find the blocks, so `reader` must be seekable.  Each
+/// block is decompressed into memory when the iterator reaches it, and its integrity check and
+/// sizes are verified.  The iterator stops after the first error.
+///
+/// Files written by xz's single-threaded encoder have just one block; see
+/// `EncoderOptions::block_size` and `EncoderOptions::threads`.
+///
+/// # Examples
+///
+/// ```
+/// use lzma::EncoderOptions;
+/// use std::io::{Cursor, Write};
+///
+/// let options = EncoderOptions::new(6).block_size(1000);
+/// let mut writer = lzma::LzmaWriter::new_compressor_with_options(Vec::new(), &options).unwrap();
+/// writer.write_all(&[b'x'; 2500]).unwrap();
+/// let compressed = writer.finish().unwrap();
+///
+/// let blocks = lzma::blocks(Cursor::new(compressed)).unwrap();
+/// let offsets: Vec<u64> = blocks.map(|block| block.unwrap().uncompressed_offset).collect();
+///
+/// assert_eq!(offsets, [0, 1000, 2000]);
+/// ```
+pub fn blocks<R: Read + Seek>(mut reader: R) -> Result<Blocks<R>, LzmaError> {
+	let info = FileInfo::from_reader(&mut reader)?;
+	let mut entries = Vec::new();
+	let mut uncompressed_offset = 0;
+
+	for stream in info.streams() {
+		for (record, offset) in stream.index.records.iter().zip(stream.block_offsets()) {
+			entries.push(BlockEntry {
+				compressed_offset: offset,
+				uncompressed_offset,
+				record: *record,
+				check_id: stream.header.check_id,
+			});
+
+			// Can't overflow, since FileInfo checks that the total fits
+			uncompressed_offset += record.uncompressed_size;
+		}
+	}
+
+	Ok(Blocks {
+		reader,
+		entries: entries.into_iter(),
+	})
+}
+
+
+/// Where to find a block, according to the index
+struct BlockEntry {
+	compressed_offset: u64,
+	uncompressed_offset: u64,
+	record: IndexRecord,
+	check_id: u8,
+}
+
+
+/// Iterator over the blocks of an `.xz` file.  See `blocks`.
+pub struct Blocks<R> {
+	reader: R,
+	entries: vec::IntoIter<BlockEntry>,
+}
+
+impl<R: Read + Seek> Blocks<R> {
+	pub fn into_inner(self) -> R {
+		self.reader
+	}
+
+	fn decode(&mut self, entry: &BlockEntry) -> Result<DecodedBlock, LzmaError> {
+		let check = Check::from_raw(entry.check_id as u32).ok_or(LzmaError::Unsupported)?;
+
+		self.reader.seek(SeekFrom::Start(entry.compressed_offset))?;
+
+		let mut header = [0u8; LZMA_BLOCK_HEADER_SIZE_MAX];
+		self.reader.read_exact(&mut header[..1])?;
+
+		let header_size = Block::header_size_from_first_byte(header[0]);
+		self.reader.read_exact(&mut header[1..header_size])?;
+
+		let mut block = Block::decode_header(&header[..header_size], check)?;
+		block.set_unpadded_size(entry.record.unpadded_size)?;
+		block.set_uncompressed_size(Some(entry.record.uncompressed_size));
+
+		// The index can claim any size, so the output isn't preallocated from it
+		let mut data = Vec::new();
+		let limit = entry.record.total_size() - header_size as u64;
+		let mut decoder = BlockDecoder::new(self.reader.by_ref().take(limit), &block)?;
+		decoder.read_to_end(&mut data)?;
+
+		Ok(DecodedBlock {
+			uncompressed_offset: entry.uncompressed_offset,
+			compressed_offset: entry.compressed_offset,
+			block: decoder.block(),
+			data,
+		})
+	}
+}
+
+impl<R: Read + Seek> Iterator for Blocks<R> {
+	type Item = Result<DecodedBlock, LzmaError>;
+
+	fn next(&mut self) -> Option<Result<DecodedBlock, LzmaError>> {
+		let entry = self.entries.next()?;
+		let result = self.decode(&entry);
+
+		if result.is_err() {
+			self.entries = Vec::new().into_iter();
+		}
+
+		Some(result)
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.entries.size_hint()
+	}
+}
@@ -0,0 +1,53 @@
+//! This module implements `CancellationToken`, which stops compression or decompression from
+//! another thread.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+
+/// A flag that makes the coders using it fail with `LzmaError::Cancelled`.
+///
+/// Clones share the same flag, so keep one and pass another to `EncoderOptions::cancellation`
+/// or `DecoderOptions::cancellation`.  The flag is checked before every call into liblzma,
+/// each of which works on at most a buffer's worth of data, so cancelling takes effect soon.
+///
+/// # Examples
+///
+/// ```
+/// use lzma::{CancellationToken, DecoderOptions, LzmaError};
+///
+/// let compressed = lzma::compress(b"Quite an experience to live in fear", 6).unwrap();
+/// let token = CancellationToken::new();
+/// let options = DecoderOptions::new().cancellation(token.clone());
+///
+/// token.cancel();
+/// assert!(matches!(lzma::decompress_with_options(&compressed, &options), Err(LzmaError::Cancelled)));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+	cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+	pub fn new() -> CancellationToken {
+		CancellationToken::default()
+	}
+
+	/// Cancels everything using this token.  This can't be undone.
+	pub fn cancel(&self) {
+		self.cancelled.store(true, Ordering::Relaxed);
+	}
+
+	pub fn is_cancelled(&self) -> bool {
+		self.cancelled.load(Ordering::Relaxed)
+	}
+}
+
+/// Tokens are equal if they are clones of each other
+impl PartialEq for CancellationToken {
+	fn eq(&self, other: &CancellationToken) -> bool {
+		Arc::ptr_eq(&self.cancelled, &other.cancelled)
+	}
+}
+
+impl Eq for CancellationToken {}
@@ -0,0 +1,159 @@
+//! This module implements `capabilities`, which reports the version of liblzma in use and what
+//! it was built to support.
+//!
+//! liblzma can be built without some filters, checks and match finders, so what's available
+//! depends on the system the program runs on rather than the one it was built on.
+
+use std::ffi::CStr;
+use lzma_sys::*;
+use error::LzmaError;
+use check::Check;
+use filter::{Filter, MatchFinder, Mode};
+
+
+/// The version and features of the liblzma in use.  See `capabilities`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+	version_number: u32,
+	version_string: String,
+	checks: Vec<Check>,
+	match_finders: Vec<MatchFinder>,
+	modes: Vec<Mode>,
+}
+
+
+/// Returns the version and features of the liblzma in use.
+///
+/// # Examples
+///
+/// ```
+/// use lzma::check::Check;
+///
+/// let capabilities = lzma::capabilities();
+///
+/// println!("liblzma {}", capabilities.version_string());
+///
+/// if !capabilities.supports_check(Check::Sha256) {
+///     println!("SHA-256 isn't available");
+/// }
+/// ```
+pub fn capabilities() -> Capabilities {
+	let version_string = unsafe { CStr::from_ptr(lzma_version_string()) };
+	let checks = [Check::None, Check::Crc32, Check::Crc64, Check::Sha256];
+	let match_finders = [MatchFinder::Hc3, MatchFinder::Hc4, MatchFinder::Bt2, MatchFinder::Bt3, MatchFinder::Bt4];
+	let modes = [Mode::Fast, Mode::Normal];
+
+	Capabilities {
+		version_number: unsafe { lzma_version_number() },
+		version_string: version_string.to_string_lossy().into_owned(),
+		checks: checks.iter().cloned().filter(|&check| check_is_supported(check)).collect(),
+		match_finders: match_finders.iter().cloned().filter(|mf| unsafe { lzma_mf_is_supported(mf.to_raw()) } != 0).collect(),
+		modes: modes.iter().cloned().filter(|mode| unsafe { lzma_mode_is_supported(mode.to_raw()) } != 0).collect(),
+	}
+}
+
+
+impl Capabilities {
+	/// The version of liblzma, encoded as major * 10000000 + minor * 10000 + patch * 10 +
+	/// stability, where stability is 0 for alpha, 1 for beta and 2 for stable releases.
+	pub fn version_number(&self) -> u32 {
+		self.version_number
+	}
+
+	/// The version of liblzma as (major, minor, patch)
+	pub fn version(&self) -> (u32, u32, u32) {
+		let n = self.version_number;
+
+		(n / 10000000, n / 10000 % 1000, n / 10 % 1000)
+	}
+
+	/// The version of liblzma as a string, e.g. "5.4.1"
+	pub fn version_string(&self) -> &str {
+		&self.version_string
+	}
+
+	/// The integrity checks liblzma can compute
+	pub fn checks(&self) -> &[Check] {
+		&self.checks
+	}
+
+	pub fn supports_check(&self, check: Check) -> bool {
+		self.checks.contains(&check)
+	}
+
+	/// The match finders the LZMA1 and LZMA2 encoders can use
+	pub fn match_finders(&self) -> &[MatchFinder] {
+		&self.match_finders
+	}
+
+	pub fn supports_match_finder(&self, mf: MatchFinder) -> bool {
+		self.match_finders.contains(&mf)
+	}
+
+	/// The compression modes the LZMA1 and LZMA2 encoders can use
+	pub fn modes(&self) -> &[Mode] {
+		&self.modes
+	}
+
+	pub fn supports_mode(&self, mode: Mode) -> bool {
+		self.modes.contains(&mode)
+	}
+
+	/// Whether liblzma can compress with the kind of filter given.  The filter's options are
+	/// ignored.
+	pub fn supports_encoder(&self, filter: &Filter) -> bool {
+		unsafe { lzma_filter_encoder_is_supported(filter.id()) != 0 }
+	}
+
+	/// Whether liblzma can decompress with the kind of filter given.  The filter's options are
+	/// ignored.
+	pub fn supports_decoder(&self, filter: &Filter) -> bool {
+		unsafe { lzma_filter_decoder_is_supported(filter.id()) != 0 }
+	}
+}
+
+
+fn check_is_supported(check: Check) -> bool {
+	unsafe { lzma_check_is_supported(check.to_raw()) != 0 }
+}
+
+/// Fails with `LzmaError::Unsupported` if liblzma can't compute `check`
+pub(crate) fn require_check(check: Check) -> Result<(), LzmaError> {
+	if check_is_supported(check) {
+		Ok(())
+	} else {
+		Err(LzmaError::Unsupported)
+	}
+}
+
+/// Fails with `LzmaError::Unsupported` if liblzma can't compress with `filters`
+pub(crate) fn require_encoder(filters: &[Filter]) -> Result<(), LzmaError> {
+	for filter in filters {
+		if unsafe { lzma_filter_encoder_is_supported(filter.id()) } == 0 {
+			return Err(LzmaError::Unsupported);
+		}
+
+		if let Filter::Lzma1(ref options) | Filter::Lzma2(ref options) = *filter {
+			let supported = unsafe {
+				lzma_mf_is_supported(options.mf.to_raw()) != 0 && lzma_mode_is_supported(options.mode.to_raw()) != 0
+			};
+
+			if !supported {
+				return Err(LzmaError::Unsupported);
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// Fails with `LzmaError::Unsupported` if liblzma can't decompress with `filters`
+pub(crate) fn require_decoder(filters: &[Filter]) -> Result<(), LzmaError> {
+	for filter in filters {
+		if unsafe { lzma_filter_decoder_is_supported(filter.id()) } == 0 {
+			return Err(LzmaError::Unsupported);
+		}
+	}
+
+	Ok(())
+}
@@ -0,0 +1,160 @@
+//! Integrity checks used by XZ streams and blocks.
+//!
+//! `Crc32` and `Crc64` compute checksums using liblzma's own implementations, so they match the
+//! check values stored in `.xz` files.  They can be fed either through `std::hash::Hasher` or
+//! as a `Write` sink.
+//!
+//! liblzma doesn't export its SHA-256 implementation, so there is no hasher for `Check::Sha256`.
+//!
+//!
+//! # Examples
+//!
+//! ```
+//! use lzma::check::Crc64;
+//! use std::io::prelude::*;
+//!
+//! let mut crc = Crc64::new();
+//! crc.write_all(b"123456789").unwrap();
+//!
+//! assert_eq!(crc.checksum(), 0x995dc9bbdf1939fa);
+//! ```
+
+use std::hash::Hasher;
+use std::io::{self, Write};
+use lzma_sys::*;
+
+
+/// The integrity check stored alongside compressed data
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Check {
+	/// No integrity check
+	None,
+	/// CRC32 (4 bytes)
+	Crc32,
+	/// CRC64 (8 bytes)
+	Crc64,
+	/// SHA-256 (32 bytes)
+	Sha256,
+}
+
+impl Check {
+	/// The size of the check value in bytes
+	pub fn size(self) -> usize {
+		match self {
+			Check::None => 0,
+			Check::Crc32 => 4,
+			Check::Crc64 => 8,
+			Check::Sha256 => 32,
+		}
+	}
+
+	pub(crate) fn to_raw(self) -> lzma_check {
+		match self {
+			Check::None => lzma_check::LzmaCheckNone,
+			Check::Crc32 => lzma_check::LzmaCheckCrc32,
+			Check::Crc64 => lzma_check::LzmaCheckCrc64,
+			Check::Sha256 => lzma_check::LzmaCheckSha256,
+		}
+	}
+
+	/// Check IDs 0-15 are valid in the XZ format, but only these four have been defined.
+	pub(crate) fn from_raw(id: u32) -> Option<Check> {
+		match id {
+			0 => Some(Check::None),
+			1 => Some(Check::Crc32),
+			4 => Some(Check::Crc64),
+			10 => Some(Check::Sha256),
+			_ => None,
+		}
+	}
+}
+
+
+/// Incremental CRC32, as used by `Check::Crc32`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Crc32 {
+	crc: u32,
+}
+
+impl Crc32 {
+	pub fn new() -> Crc32 {
+		Crc32 {
+			crc: 0,
+		}
+	}
+
+	pub fn update(&mut self, buf: &[u8]) {
+		self.crc = unsafe { lzma_crc32(buf.as_ptr(), buf.len(), self.crc) };
+	}
+
+	/// The checksum of all the data given so far
+	pub fn checksum(&self) -> u32 {
+		self.crc
+	}
+}
+
+impl Hasher for Crc32 {
+	fn finish(&self) -> u64 {
+		self.crc as u64
+	}
+
+	fn write(&mut self, bytes: &[u8]) {
+		self.update(bytes);
+	}
+}
+
+impl Write for Crc32 {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.update(buf);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+
+/// Incremental CRC64 (ECMA-182), as used by `Check::Crc64`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Crc64 {
+	crc: u64,
+}
+
+impl Crc64 {
+	pub fn new() -> Crc64 {
+		Crc64 {
+			crc: 0,
+		}
+	}
+
+	pub fn update(&mut self, buf: &[u8]) {
+		self.crc = unsafe { lzma_crc64(buf.as_ptr(), buf.len(), self.crc) };
+	}
+
+	/// The checksum of all the data given so far
+	pub fn checksum(&self) -> u64 {
+		self.crc
+	}
+}
+
+impl Hasher for Crc64 {
+	fn finish(&self) -> u64 {
+		self.crc
+	}
+
+	fn write(&mut self, bytes: &[u8]) {
+		self.update(bytes);
+	}
+}
+
+impl Write for Crc64 {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.update(buf);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
@@ -0,0 +1,117 @@
+//! This module implements `detect_format`, which guesses the format of compressed data from its
+//! first few bytes.
+
+use xz::{StreamHeader, STREAM_HEADER_SIZE};
+use ::Format;
+
+
+const XZ_MAGIC: [u8; 6] = [0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const LZIP_MAGIC: [u8; 4] = [b'L', b'Z', b'I', b'P'];
+
+/// Size of the LZMA_Alone header: properties, dictionary size and uncompressed size
+const LZMA_ALONE_HEADER_SIZE: usize = 13;
+
+
+/// Guesses the format of compressed data from its first few bytes.
+///
+/// `.xz` and `.lz` data start with magic bytes, so they are easy to recognise.  The legacy
+/// `.lzma` format has no magic bytes; its header is sanity checked the same way liblzma does
+/// when auto-detecting.  Raw streams are recognised when they look like they start with an LZMA2
+/// chunk, as produced by xz's default filter chain.  Only the latter two can be mistaken for
+/// other data, so treat them as hints rather than certainties.
+///
+/// 13 bytes are enough to tell the formats apart.  Returns None if nothing matches.
+///
+/// # Examples
+///
+/// ```
+/// use lzma::Format;
+///
+/// let compressed = lzma::compress(b"Nothing the god of biomechanics wouldn't let you in heaven for", 6).unwrap();
+///
+/// assert_eq!(lzma::detect_format(&compressed), Some(Format::Xz));
+/// assert_eq!(lzma::detect_format(b"Just some text"), None);
+/// ```
+pub fn detect_format(buf: &[u8]) -> Option<Format> {
+	if is_xz(buf) {
+		Some(Format::Xz)
+	} else if is_lzip(buf) {
+		Some(Format::Lzip)
+	} else if is_lzma_alone(buf) {
+		Some(Format::Lzma)
+	} else if is_raw_lzma2(buf) {
+		Some(Format::Raw)
+	} else {
+		None
+	}
+}
+
+
+fn is_xz(buf: &[u8]) -> bool {
+	if buf.len() >= STREAM_HEADER_SIZE {
+		StreamHeader::parse(buf).is_ok()
+	} else {
+		buf.starts_with(&XZ_MAGIC)
+	}
+}
+
+fn is_lzip(buf: &[u8]) -> bool {
+	if buf.len() < 6 || !buf.starts_with(&LZIP_MAGIC) {
+		return false;
+	}
+
+	// Versions 0 and 1 exist.  The dictionary size is a power of two between 4 KiB and 512 MiB,
+	// minus up to 7/16 of it, but never less than 4 KiB.
+	let version = buf[4];
+	let b2log = buf[5] & 0x1f;
+	let fracnum = buf[5] >> 5;
+
+	version <= 1 && (12..=29).contains(&b2log) && !(b2log == 12 && fracnum > 0)
+}
+
+fn is_lzma_alone(buf: &[u8]) -> bool {
+	if buf.len() < LZMA_ALONE_HEADER_SIZE || !is_lzma_properties(buf[0]) {
+		return false;
+	}
+
+	let dict_size = u32::from_le_bytes([buf[1], buf[2], buf[3], buf[4]]);
+	let mut uncompressed_size = [0u8; 8];
+	uncompressed_size.copy_from_slice(&buf[5..13]);
+	let uncompressed_size = u64::from_le_bytes(uncompressed_size);
+
+	// Like liblzma, only accept dictionary sizes of 2^n or 2^n + 2^(n-1), and known sizes
+	// below 256 GiB.  Anything else is very unlikely to have been made by an LZMA encoder.
+	let mut d = dict_size.wrapping_sub(1);
+	d |= d >> 2;
+	d |= d >> 3;
+	d |= d >> 4;
+	d |= d >> 8;
+	d |= d >> 16;
+	let dict_size_ok = dict_size == u32::MAX || d.wrapping_add(1) == dict_size;
+
+	dict_size_ok && (uncompressed_size == u64::MAX || uncompressed_size < 1 << 38)
+}
+
+fn is_raw_lzma2(buf: &[u8]) -> bool {
+	match buf.first() {
+		// An uncompressed chunk that resets the dictionary
+		Some(&0x01) => buf.len() >= 3,
+		// An LZMA chunk that resets everything, which includes the properties byte.  The range
+		// coder's output that follows always starts with a zero byte.
+		Some(&control) if control >= 0xe0 => buf.len() >= 7 && is_lzma_properties(buf[5]) && buf[6] == 0,
+		_ => false,
+	}
+}
+
+/// Whether `b` is a valid LZMA properties byte, which encodes lc, lp and pb
+fn is_lzma_properties(b: u8) -> bool {
+	if b > (4 * 5 + 4) * 9 + 8 {
+		return false;
+	}
+
+	let lc = b % 9;
+	let lp = (b / 9) % 5;
+
+	// liblzma's limit
+	lc + lp <= 4
+}
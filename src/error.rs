@@ -18,10 +18,20 @@ pub enum LzmaError {
 	Format,
 	/// Unsupported compression options
 	Options,
+	/// A filter, check, match finder or mode isn't supported by the liblzma in use.
+	/// See `lzma::capabilities`.
+	Unsupported,
 	/// Corrupt data
 	Data,
 	/// Data looks truncated
 	Buf,
+	/// Decompressed output exceeded the size or ratio limit set in `DecoderOptions`
+	OutputLimit,
+	/// Something other than stream padding followed the end of the compressed data.  Only
+	/// reported with `TrailingData::Reject`.
+	TrailingData,
+	/// Stopped by a `CancellationToken` or deadline
+	Cancelled,
 	/// std::io::Error
 	Io(IoError),
 	/// An unknown error
@@ -35,8 +45,12 @@ impl fmt::Display for LzmaError {
 			LzmaError::MemLimit => write!(f, "Memory limit would be violated"),
 			LzmaError::Format => write!(f, "XZ magic bytes were not found"),
 			LzmaError::Options => write!(f, "Unsupported compression options"),
+			LzmaError::Unsupported => write!(f, "Not supported by this build of liblzma"),
 			LzmaError::Data => write!(f, "Corrupt data"),
 			LzmaError::Buf => write!(f, "Data look like it was truncated or possibly corrupt"),
+			LzmaError::OutputLimit => write!(f, "Decompressed output exceeded the configured limit"),
+			LzmaError::TrailingData => write!(f, "Unexpected data after the end of the compressed stream"),
+			LzmaError::Cancelled => write!(f, "Operation was cancelled"),
 			LzmaError::Io(ref err) => write!(f, "{}", err),
 			LzmaError::Other => write!(f, "Unknown error"),
 		}
@@ -50,8 +64,12 @@ impl StdError for LzmaError {
 			LzmaError::MemLimit => "Memory limit would be violated",
 			LzmaError::Format => "XZ magic bytes were not found",
 			LzmaError::Options => "Unsupported compression options",
+			LzmaError::Unsupported => "Not supported by this build of liblzma",
 			LzmaError::Data => "Corrupt data",
 			LzmaError::Buf => "Data look like it was truncated or possibly corrupt",
+			LzmaError::OutputLimit => "Decompressed output exceeded the configured limit",
+			LzmaError::TrailingData => "Unexpected data after the end of the compressed stream",
+			LzmaError::Cancelled => "Operation was cancelled",
 			LzmaError::Io(..) => "IO error",
 			LzmaError::Other => "Unknown error",
 		}
@@ -66,8 +84,16 @@ impl StdError for LzmaError {
 }
 
 impl From<IoError> for LzmaError {
+	/// `LzmaReader` and `LzmaWriter` wrap their errors in `io::Error`.  Output limits, trailing
+	/// data and cancellation are unwrapped again, so that one-shot functions like `decompress`
+	/// report them directly.  Everything else stays wrapped in `LzmaError::Io`.
 	fn from(err: IoError) -> LzmaError {
-		LzmaError::Io(err)
+		match err.get_ref().and_then(|inner| inner.downcast_ref::<LzmaError>()) {
+			Some(&LzmaError::OutputLimit) => LzmaError::OutputLimit,
+			Some(&LzmaError::TrailingData) => LzmaError::TrailingData,
+			Some(&LzmaError::Cancelled) => LzmaError::Cancelled,
+			_ => LzmaError::Io(err),
+		}
 	}
 }
 
@@ -81,7 +107,9 @@ impl From<lzma_ret> for LzmaLibResult {
 			lzma_ret::LzmaOk => Ok(ret),
 			lzma_ret::LzmaStreamEnd => Ok(ret),
 			lzma_ret::LzmaNoCheck => Ok(ret),
-			lzma_ret::LzmaUnsupportedCheck => Ok(ret), // NOTE: This is an error in some cases.  Not sure how to handle properly.
+			// Decoders only return this with LZMA_TELL_UNSUPPORTED_CHECK, which isn't used, so it
+			// comes from setting up a coder with a check liblzma can't do
+			lzma_ret::LzmaUnsupportedCheck => Err(LzmaError::Unsupported),
 			lzma_ret::LzmaGetCheck => Ok(ret),
 			lzma_ret::LzmaMemError => Err(LzmaError::Mem),
 			lzma_ret::LzmaMemlimitError => Err(LzmaError::MemLimit),
@@ -0,0 +1,744 @@
+//! Filter chains.
+//!
+//! Every XZ block is encoded with a chain of up to four filters.  The last filter in the chain
+//! is always the one doing the actual compression (LZMA1 or LZMA2); any filters before it are
+//! simple transforms (like the BCJ filters for executables) that help it compress better.
+//!
+//! # Examples
+//!
+//! ```
+//! use lzma::filter::{Filter, FilterChain, BcjOptions, LzmaOptions};
+//!
+//! let mut filters = FilterChain::new();
+//! filters.push(Filter::X86(BcjOptions::default()));
+//! filters.push(Filter::Lzma2(LzmaOptions::preset(6).unwrap()));
+//! ```
+//!
+//! With the `filter-strings` feature, which needs liblzma 5.4 or later, filter chains can also
+//! be written the way xz's `--filters` option takes them:
+//!
+//! ```
+//! # #[cfg(feature = "filter-strings")] {
+//! use lzma::filter::FilterChain;
+//!
+//! let filters: FilterChain = "x86 lzma2:preset=6e,dict=64MiB".parse().unwrap();
+//! assert_eq!(filters.filters().len(), 2);
+//!
+//! assert!(filters.to_filter_string().unwrap().starts_with("x86 lzma2:"));
+//!
+//! let err = "x86 lzma2:dict=64Mib".parse::<FilterChain>().unwrap_err();
+//! assert_eq!(err.position(), 17);
+//! # }
+//! ```
+
+use lzma_sys::*;
+use error::{LzmaError, LzmaLibResult};
+use std::alloc::{self, Layout};
+#[cfg(feature = "filter-strings")]
+use std::error;
+#[cfg(feature = "filter-strings")]
+use std::ffi::{CStr, CString};
+#[cfg(feature = "filter-strings")]
+use std::fmt;
+use std::os::raw::c_void;
+use std::ptr;
+#[cfg(feature = "filter-strings")]
+use std::str::FromStr;
+
+
+/// LZMA compression mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Mode {
+	Fast,
+	Normal,
+}
+
+/// LZMA match finder
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MatchFinder {
+	/// Hash Chain with 2- and 3-byte hashing
+	Hc3,
+	/// Hash Chain with 2-, 3-, and 4-byte hashing
+	Hc4,
+	/// Binary Tree with 2-byte hashing
+	Bt2,
+	/// Binary Tree with 2- and 3-byte hashing
+	Bt3,
+	/// Binary Tree with 2-, 3-, and 4-byte hashing
+	Bt4,
+}
+
+impl Mode {
+	pub(crate) fn to_raw(self) -> u32 {
+		match self {
+			Mode::Fast => 1,
+			Mode::Normal => 2,
+		}
+	}
+
+	pub(crate) fn from_raw(mode: u32) -> Option<Mode> {
+		match mode {
+			1 => Some(Mode::Fast),
+			2 => Some(Mode::Normal),
+			_ => None,
+		}
+	}
+}
+
+impl MatchFinder {
+	pub(crate) fn to_raw(self) -> u32 {
+		match self {
+			MatchFinder::Hc3 => 0x03,
+			MatchFinder::Hc4 => 0x04,
+			MatchFinder::Bt2 => 0x12,
+			MatchFinder::Bt3 => 0x13,
+			MatchFinder::Bt4 => 0x14,
+		}
+	}
+
+	pub(crate) fn from_raw(mf: u32) -> Option<MatchFinder> {
+		match mf {
+			0x03 => Some(MatchFinder::Hc3),
+			0x04 => Some(MatchFinder::Hc4),
+			0x12 => Some(MatchFinder::Bt2),
+			0x13 => Some(MatchFinder::Bt3),
+			0x14 => Some(MatchFinder::Bt4),
+			_ => None,
+		}
+	}
+}
+
+
+/// Options for the LZMA1 and LZMA2 filters.
+///
+/// See liblzma's `lzma12.h` for a description of each field.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LzmaOptions {
+	/// Dictionary size in bytes
+	pub dict_size: u32,
+	/// Number of literal context bits
+	pub lc: u32,
+	/// Number of literal position bits
+	pub lp: u32,
+	/// Number of position bits
+	pub pb: u32,
+	pub mode: Mode,
+	/// Nice length of a match
+	pub nice_len: u32,
+	pub mf: MatchFinder,
+	/// Maximum search depth in the match finder.  0 lets liblzma pick a default.
+	pub depth: u32,
+}
+
+impl LzmaOptions {
+	/// Options corresponding to one of xz's presets.
+	///
+	/// preset is [0-9] and corresponds to xz's presets.
+	/// Binary-or with EXTREME_PRESET for --extreme (e.g. 9 | EXTREME_PRESET).
+	pub fn preset(preset: u32) -> Result<LzmaOptions, LzmaError> {
+		let mut raw = lzma_options_lzma::new();
+
+		// lzma_lzma_preset returns true on error
+		if unsafe { lzma_lzma_preset(&mut raw, preset) } != 0 {
+			return Err(LzmaError::Options);
+		}
+
+		LzmaOptions::from_raw(&raw)
+	}
+
+	pub(crate) fn to_raw(&self) -> lzma_options_lzma {
+		let mut raw = lzma_options_lzma::new();
+
+		raw.dict_size = self.dict_size;
+		raw.lc = self.lc;
+		raw.lp = self.lp;
+		raw.pb = self.pb;
+		raw.mode = self.mode.to_raw();
+		raw.nice_len = self.nice_len;
+		raw.mf = self.mf.to_raw();
+		raw.depth = self.depth;
+
+		raw
+	}
+
+	fn from_raw(raw: &lzma_options_lzma) -> Result<LzmaOptions, LzmaError> {
+		Ok(LzmaOptions {
+			dict_size: raw.dict_size,
+			lc: raw.lc,
+			lp: raw.lp,
+			pb: raw.pb,
+			mode: Mode::from_raw(raw.mode).ok_or(LzmaError::Options)?,
+			nice_len: raw.nice_len,
+			mf: MatchFinder::from_raw(raw.mf).ok_or(LzmaError::Options)?,
+			depth: raw.depth,
+		})
+	}
+
+	/// liblzma's property decoders only fill in the fields stored in the properties (dict_size
+	/// for LZMA2, and also lc/lp/pb for LZMA1), leaving the rest uninitialized.  The encoder-only
+	/// fields are taken from the defaults instead.
+	fn from_raw_properties(raw: &lzma_options_lzma, lzma1: bool) -> LzmaOptions {
+		let mut options = LzmaOptions {
+			dict_size: raw.dict_size,
+			..LzmaOptions::default()
+		};
+
+		if lzma1 {
+			options.lc = raw.lc;
+			options.lp = raw.lp;
+			options.pb = raw.pb;
+		}
+
+		options
+	}
+}
+
+impl Default for LzmaOptions {
+	/// The options of xz's default preset (6)
+	fn default() -> LzmaOptions {
+		LzmaOptions::preset(6).expect("preset 6 is always valid")
+	}
+}
+
+
+/// Options for the BCJ (branch/call/jump) filters
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BcjOptions {
+	/// Start offset for conversions.  Almost always 0.
+	pub start_offset: u32,
+}
+
+
+/// Options for the Delta filter
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DeltaOptions {
+	/// Delta distance in bytes [1-256]
+	pub distance: u32,
+}
+
+impl Default for DeltaOptions {
+	fn default() -> DeltaOptions {
+		DeltaOptions {
+			distance: 1,
+		}
+	}
+}
+
+
+/// A single filter in a `FilterChain`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Filter {
+	/// LZMA1, as used by the legacy `.lzma` format.  Not supported in `.xz` files.
+	Lzma1(LzmaOptions),
+	Lzma2(LzmaOptions),
+	Delta(DeltaOptions),
+	X86(BcjOptions),
+	PowerPc(BcjOptions),
+	Ia64(BcjOptions),
+	Arm(BcjOptions),
+	ArmThumb(BcjOptions),
+	Sparc(BcjOptions),
+	Arm64(BcjOptions),
+}
+
+impl Filter {
+	/// The Filter ID used to identify this filter in XZ headers
+	pub fn id(&self) -> u64 {
+		match *self {
+			Filter::Lzma1(_) => LZMA_FILTER_LZMA1,
+			Filter::Lzma2(_) => LZMA_FILTER_LZMA2,
+			Filter::Delta(_) => LZMA_FILTER_DELTA,
+			Filter::X86(_) => LZMA_FILTER_X86,
+			Filter::PowerPc(_) => LZMA_FILTER_POWERPC,
+			Filter::Ia64(_) => LZMA_FILTER_IA64,
+			Filter::Arm(_) => LZMA_FILTER_ARM,
+			Filter::ArmThumb(_) => LZMA_FILTER_ARMTHUMB,
+			Filter::Sparc(_) => LZMA_FILTER_SPARC,
+			Filter::Arm64(_) => LZMA_FILTER_ARM64,
+		}
+	}
+
+	/// Encodes the filter's properties, the options a decoder needs, the way container formats
+	/// store them.  For example, LZMA1 has 5 bytes of properties as used by `.7z` and `.zip`, and
+	/// LZMA2 has a single byte holding the dictionary size.
+	pub fn properties(&self) -> Result<Vec<u8>, LzmaError> {
+		let raw = self.to_raw()?;
+		let mut size = 0;
+
+		unsafe {
+			LzmaLibResult::from(lzma_properties_size(&mut size, raw.as_ptr())).map(|_| ())?;
+
+			let mut props = vec![0u8; size as usize];
+			LzmaLibResult::from(lzma_properties_encode(raw.as_ptr(), props.as_mut_ptr())).map(|_| ())?;
+
+			Ok(props)
+		}
+	}
+
+	/// Decodes the properties of the filter with the given Filter ID.  Options that only matter
+	/// to the encoder get their default values.
+	pub fn from_properties(id: u64, props: &[u8]) -> Result<Filter, LzmaError> {
+		let mut filters = [
+			lzma_filter { id, options: ptr::null_mut() },
+			lzma_filter { id: LZMA_VLI_UNKNOWN, options: ptr::null_mut() },
+		];
+
+		let allocator = rust_allocator();
+
+		unsafe {
+			LzmaLibResult::from(lzma_properties_decode(&mut filters[0], &allocator, props.as_ptr(), props.len())).map(|_| ())?;
+
+			let filter = Filter::from_raw(&filters[0], true);
+			free_raw_filters(&mut filters);
+			filter
+		}
+	}
+
+	/// Encodes the Filter Flags field that describes this filter in an XZ block header: the
+	/// Filter ID and the size of the properties, followed by the properties themselves.
+	pub fn filter_flags(&self) -> Result<Vec<u8>, LzmaError> {
+		let raw = self.to_raw()?;
+		let mut size = 0;
+
+		unsafe {
+			LzmaLibResult::from(lzma_filter_flags_size(&mut size, raw.as_ptr())).map(|_| ())?;
+
+			let mut flags = vec![0u8; size as usize];
+			let mut out_pos = 0;
+			LzmaLibResult::from(lzma_filter_flags_encode(raw.as_ptr(), flags.as_mut_ptr(), &mut out_pos, flags.len())).map(|_| ())?;
+
+			Ok(flags)
+		}
+	}
+
+	/// Decodes a Filter Flags field from the start of `buf`.  Returns the filter along with the
+	/// number of bytes the field took up.
+	pub fn from_filter_flags(buf: &[u8]) -> Result<(Filter, usize), LzmaError> {
+		let mut filters = [
+			lzma_filter { id: LZMA_VLI_UNKNOWN, options: ptr::null_mut() },
+			lzma_filter { id: LZMA_VLI_UNKNOWN, options: ptr::null_mut() },
+		];
+		let mut in_pos = 0;
+		let allocator = rust_allocator();
+
+		unsafe {
+			LzmaLibResult::from(lzma_filter_flags_decode(&mut filters[0], &allocator, buf.as_ptr(), &mut in_pos, buf.len())).map(|_| ())?;
+
+			let filter = Filter::from_raw(&filters[0], true);
+			free_raw_filters(&mut filters);
+			Ok((filter?, in_pos))
+		}
+	}
+
+	/// A single filter in the form liblzma expects
+	fn to_raw(&self) -> Result<RawFilters, LzmaError> {
+		FilterChain::from(vec![self.clone()]).to_raw()
+	}
+
+	/// Converts a filter as returned by liblzma.  If `properties` is true, the filter was produced
+	/// by one of liblzma's property decoders, so only decoder-relevant options are initialized.
+	unsafe fn from_raw(filter: &lzma_filter, properties: bool) -> Result<Filter, LzmaError> {
+		let bcj = || {
+			if filter.options.is_null() {
+				BcjOptions::default()
+			} else {
+				BcjOptions {
+					start_offset: (*(filter.options as *const lzma_options_bcj)).start_offset,
+				}
+			}
+		};
+		let lzma = |lzma1: bool| {
+			if filter.options.is_null() {
+				return Err(LzmaError::Options);
+			}
+
+			let raw = &*(filter.options as *const lzma_options_lzma);
+
+			if properties {
+				Ok(LzmaOptions::from_raw_properties(raw, lzma1))
+			} else {
+				LzmaOptions::from_raw(raw)
+			}
+		};
+
+		Ok(match filter.id {
+			LZMA_FILTER_LZMA1 => Filter::Lzma1(lzma(true)?),
+			LZMA_FILTER_LZMA2 => Filter::Lzma2(lzma(false)?),
+			LZMA_FILTER_DELTA => {
+				if filter.options.is_null() {
+					return Err(LzmaError::Options);
+				}
+
+				Filter::Delta(DeltaOptions {
+					distance: (*(filter.options as *const lzma_options_delta)).dist,
+				})
+			},
+			LZMA_FILTER_X86 => Filter::X86(bcj()),
+			LZMA_FILTER_POWERPC => Filter::PowerPc(bcj()),
+			LZMA_FILTER_IA64 => Filter::Ia64(bcj()),
+			LZMA_FILTER_ARM => Filter::Arm(bcj()),
+			LZMA_FILTER_ARMTHUMB => Filter::ArmThumb(bcj()),
+			LZMA_FILTER_SPARC => Filter::Sparc(bcj()),
+			LZMA_FILTER_ARM64 => Filter::Arm64(bcj()),
+			_ => return Err(LzmaError::Options),
+		})
+	}
+}
+
+
+/// A chain of up to four filters.  The last filter must be LZMA1 or LZMA2.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FilterChain {
+	filters: Vec<Filter>,
+}
+
+impl FilterChain {
+	/// An empty filter chain
+	pub fn new() -> FilterChain {
+		FilterChain {
+			filters: Vec::new(),
+		}
+	}
+
+	/// A chain consisting of a single LZMA2 filter using one of xz's presets
+	pub fn preset(preset: u32) -> Result<FilterChain, LzmaError> {
+		Ok(FilterChain::from(vec![Filter::Lzma2(LzmaOptions::preset(preset)?)]))
+	}
+
+	/// Appends a filter to the end of the chain
+	pub fn push(&mut self, filter: Filter) {
+		self.filters.push(filter);
+	}
+
+	pub fn filters(&self) -> &[Filter] {
+		&self.filters
+	}
+
+	/// The Filter ID and properties of each filter, in order.  See `Filter::properties`.
+	pub fn properties(&self) -> Result<Vec<(u64, Vec<u8>)>, LzmaError> {
+		FilterChain::check_len(self.filters.len())?;
+
+		self.filters.iter().map(|filter| Ok((filter.id(), filter.properties()?))).collect()
+	}
+
+	/// Decodes a chain from the Filter ID and properties of each filter.  See
+	/// `Filter::from_properties`.
+	pub fn from_properties(filters: &[(u64, &[u8])]) -> Result<FilterChain, LzmaError> {
+		FilterChain::check_len(filters.len())?;
+
+		filters.iter().map(|&(id, props)| Filter::from_properties(id, props)).collect::<Result<Vec<_>, _>>().map(FilterChain::from)
+	}
+
+	/// Encodes the Filter Flags fields of every filter, as they appear one after another in an
+	/// XZ block header.  See `Filter::filter_flags`.
+	pub fn filter_flags(&self) -> Result<Vec<u8>, LzmaError> {
+		FilterChain::check_len(self.filters.len())?;
+
+		let mut flags = Vec::new();
+
+		for filter in &self.filters {
+			flags.extend_from_slice(&filter.filter_flags()?);
+		}
+
+		Ok(flags)
+	}
+
+	/// Decodes `count` Filter Flags fields from the start of `buf`.  Returns the chain along
+	/// with the number of bytes the fields took up.
+	pub fn from_filter_flags(buf: &[u8], count: usize) -> Result<(FilterChain, usize), LzmaError> {
+		FilterChain::check_len(count)?;
+
+		let mut chain = FilterChain::new();
+		let mut pos = 0;
+
+		for _ in 0..count {
+			let (filter, len) = Filter::from_filter_flags(&buf[pos..])?;
+			chain.push(filter);
+			pos += len;
+		}
+
+		Ok((chain, pos))
+	}
+
+	/// Formats the chain in the syntax of xz's `--filters` option, listing every option.  This
+	/// is the reverse of parsing a `FilterChain` from a string.
+	///
+	/// Fails with `LzmaError::Options` if liblzma rejects the chain, e.g. because it has too
+	/// many filters.
+	#[cfg(feature = "filter-strings")]
+	pub fn to_filter_string(&self) -> Result<String, LzmaError> {
+		let raw = self.to_raw()?;
+		let allocator = rust_allocator();
+		let mut s = ptr::null_mut();
+
+		unsafe {
+			LzmaLibResult::from(lzma_str_from_filters(&mut s, raw.as_ptr(), LZMA_STR_ENCODER, &allocator)).map(|_| ())?;
+
+			let result = CStr::from_ptr(s).to_string_lossy().into_owned();
+			(allocator.free)(allocator.opaque, s as *mut c_void);
+			Ok(result)
+		}
+	}
+
+	/// How much memory liblzma needs to decode with this chain
+	pub(crate) fn decoder_memusage(&self) -> Result<u64, LzmaError> {
+		let raw = self.to_raw()?;
+
+		match unsafe { lzma_raw_decoder_memusage(raw.as_ptr()) } {
+			u64::MAX => Err(LzmaError::Options),
+			usage => Ok(usage),
+		}
+	}
+
+	/// Builds the `lzma_filter` array expected by liblzma
+	pub(crate) fn to_raw(&self) -> Result<RawFilters, LzmaError> {
+		FilterChain::check_len(self.filters.len())?;
+
+		let mut raw = RawFilters {
+			filters: Vec::with_capacity(self.filters.len() + 1),
+			_options: Vec::with_capacity(self.filters.len()),
+		};
+
+		for filter in &self.filters {
+			let options = match *filter {
+				Filter::Lzma1(ref options) | Filter::Lzma2(ref options) => RawOptions::Lzma(Box::new(options.to_raw())),
+				Filter::Delta(ref options) => {
+					let mut delta = lzma_options_delta::new();
+					delta.dist = options.distance;
+					RawOptions::Delta(Box::new(delta))
+				},
+				Filter::X86(ref options) | Filter::PowerPc(ref options) | Filter::Ia64(ref options) |
+				Filter::Arm(ref options) | Filter::ArmThumb(ref options) | Filter::Sparc(ref options) |
+				Filter::Arm64(ref options) => RawOptions::Bcj(Box::new(lzma_options_bcj {
+					start_offset: options.start_offset,
+				})),
+			};
+
+			raw.filters.push(lzma_filter {
+				id: filter.id(),
+				options: options.as_ptr(),
+			});
+			raw._options.push(options);
+		}
+
+		raw.filters.push(lzma_filter {
+			id: LZMA_VLI_UNKNOWN,
+			options: ptr::null_mut(),
+		});
+
+		Ok(raw)
+	}
+
+	/// Chains have between one and four filters
+	fn check_len(len: usize) -> Result<(), LzmaError> {
+		if len == 0 || len > LZMA_FILTERS_MAX {
+			return Err(LzmaError::Options);
+		}
+
+		Ok(())
+	}
+
+	/// Converts an `LZMA_VLI_UNKNOWN` terminated array of filters returned by liblzma.
+	///
+	/// `properties` must be true if the array came from one of liblzma's property decoders
+	/// (e.g. `lzma_block_header_decode`).
+	pub(crate) unsafe fn from_raw(filters: *const lzma_filter, properties: bool) -> Result<FilterChain, LzmaError> {
+		let mut chain = FilterChain::new();
+
+		for i in 0..LZMA_FILTERS_MAX {
+			let filter = &*filters.add(i);
+
+			if filter.id == LZMA_VLI_UNKNOWN {
+				break;
+			}
+
+			chain.push(Filter::from_raw(filter, properties)?);
+		}
+
+		Ok(chain)
+	}
+}
+
+impl From<Vec<Filter>> for FilterChain {
+	fn from(filters: Vec<Filter>) -> FilterChain {
+		FilterChain {
+			filters,
+		}
+	}
+}
+
+#[cfg(feature = "filter-strings")]
+impl FromStr for FilterChain {
+	type Err = ParseFilterChainError;
+
+	/// Parses a filter chain in the syntax of xz's `--filters` option, such as
+	/// `x86 lzma2:preset=6e,dict=64MiB`.  A lone preset such as `6e` is accepted too.
+	/// See liblzma's `lzma_str_to_filters` for the details.
+	fn from_str(s: &str) -> Result<FilterChain, ParseFilterChainError> {
+		let s = CString::new(s).map_err(|err| ParseFilterChainError {
+			position: err.nul_position(),
+			message: "Unexpected null byte".to_string(),
+		})?;
+		let mut filters: Vec<lzma_filter> = (0..=LZMA_FILTERS_MAX).map(|_| lzma_filter {
+			id: LZMA_VLI_UNKNOWN,
+			options: ptr::null_mut(),
+		}).collect();
+		let mut error_pos = 0;
+		let allocator = rust_allocator();
+
+		unsafe {
+			// On failure nothing is left allocated in filters
+			let message = lzma_str_to_filters(s.as_ptr(), &mut error_pos, filters.as_mut_ptr(), LZMA_STR_ALL_FILTERS, &allocator);
+
+			if !message.is_null() {
+				return Err(ParseFilterChainError {
+					position: error_pos.max(0) as usize,
+					message: CStr::from_ptr(message).to_string_lossy().into_owned(),
+				});
+			}
+
+			let chain = FilterChain::from_raw(filters.as_ptr(), false);
+			free_raw_filters(&mut filters);
+
+			chain.map_err(|_| ParseFilterChainError {
+				position: 0,
+				message: "Unsupported filter".to_string(),
+			})
+		}
+	}
+}
+
+
+/// An error from parsing a `FilterChain` string
+#[cfg(feature = "filter-strings")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseFilterChainError {
+	position: usize,
+	message: String,
+}
+
+#[cfg(feature = "filter-strings")]
+impl ParseFilterChainError {
+	/// The byte offset in the string where the error was found
+	pub fn position(&self) -> usize {
+		self.position
+	}
+
+	/// liblzma's description of the error
+	pub fn message(&self) -> &str {
+		&self.message
+	}
+}
+
+#[cfg(feature = "filter-strings")]
+impl fmt::Display for ParseFilterChainError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{} at position {}", self.message, self.position)
+	}
+}
+
+#[cfg(feature = "filter-strings")]
+impl error::Error for ParseFilterChainError {
+	fn description(&self) -> &str {
+		&self.message
+	}
+}
+
+
+enum RawOptions {
+	Lzma(Box<lzma_options_lzma>),
+	Bcj(Box<lzma_options_bcj>),
+	Delta(Box<lzma_options_delta>),
+}
+
+impl RawOptions {
+	fn as_ptr(&self) -> *mut c_void {
+		match *self {
+			RawOptions::Lzma(ref options) => &**options as *const lzma_options_lzma as *mut c_void,
+			RawOptions::Bcj(ref options) => &**options as *const lzma_options_bcj as *mut c_void,
+			RawOptions::Delta(ref options) => &**options as *const lzma_options_delta as *mut c_void,
+		}
+	}
+}
+
+
+/// An `lzma_filter` array along with the option structs it points to.
+///
+/// Everything is heap allocated, so the pointers stay valid when this is moved.
+pub(crate) struct RawFilters {
+	filters: Vec<lzma_filter>,
+	_options: Vec<RawOptions>,
+}
+
+impl RawFilters {
+	pub(crate) fn as_ptr(&self) -> *const lzma_filter {
+		self.filters.as_ptr()
+	}
+
+	pub(crate) fn as_mut_ptr(&mut self) -> *mut lzma_filter {
+		self.filters.as_mut_ptr()
+	}
+}
+
+
+/// An `lzma_allocator` using Rust's global allocator.  Memory liblzma hands over to us is
+/// allocated with this, so that we know how to free it.
+pub(crate) fn rust_allocator() -> lzma_allocator {
+	lzma_allocator {
+		alloc: rust_alloc,
+		free: rust_free,
+		opaque: ptr::null_mut(),
+	}
+}
+
+// Every allocation starts with its size, padded to keep the rest aligned like malloc does
+const ALLOC_HEADER_SIZE: usize = 16;
+
+extern "C" fn rust_alloc(_opaque: *mut c_void, nmemb: usize, size: usize) -> *mut c_void {
+	let layout = nmemb.checked_mul(size)
+		.and_then(|size| size.checked_add(ALLOC_HEADER_SIZE))
+		.and_then(|size| Layout::from_size_align(size, ALLOC_HEADER_SIZE).ok());
+
+	match layout {
+		Some(layout) => unsafe {
+			let ptr = alloc::alloc(layout);
+
+			if ptr.is_null() {
+				return ptr::null_mut();
+			}
+
+			(ptr as *mut usize).write(layout.size());
+			ptr.add(ALLOC_HEADER_SIZE) as *mut c_void
+		},
+		None => ptr::null_mut(),
+	}
+}
+
+extern "C" fn rust_free(_opaque: *mut c_void, ptr: *mut c_void) {
+	if ptr.is_null() {
+		return;
+	}
+
+	unsafe {
+		let ptr = (ptr as *mut u8).sub(ALLOC_HEADER_SIZE);
+		let size = (ptr as *const usize).read();
+		alloc::dealloc(ptr, Layout::from_size_align_unchecked(size, ALLOC_HEADER_SIZE));
+	}
+}
+
+
+/// Frees the options of an array of filters that liblzma allocated with `rust_allocator` (e.g.
+/// in `lzma_block_header_decode`).  This does what `lzma_filters_free` does, which needs
+/// liblzma 5.4.
+pub(crate) unsafe fn free_raw_filters(filters: &mut [lzma_filter]) {
+	for filter in filters {
+		if filter.id == LZMA_VLI_UNKNOWN {
+			break;
+		}
+
+		rust_free(ptr::null_mut(), filter.options);
+		filter.id = LZMA_VLI_UNKNOWN;
+		filter.options = ptr::null_mut();
+	}
+}
+
@@ -0,0 +1,134 @@
+//! This module implements `Index`, which reads the index of an `.xz` file.
+//!
+//! Every XZ stream ends with an index listing the size of each of its blocks.  Reading it only
+//! requires looking at the end of each stream, so it's a cheap way to find out how big a file
+//! will be once decompressed, or how it is split into blocks.
+//!
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use lzma::index::Index;
+//! use std::fs::File;
+//!
+//! let mut f = File::open("foo.xz").unwrap();
+//! let index = Index::from_reader(&mut f).unwrap();
+//!
+//! println!("{} blocks, {} bytes uncompressed", index.block_count(), index.uncompressed_size());
+//! ```
+
+use std::io::{Read, Seek};
+use std::ptr;
+use lzma_sys::*;
+use error::{LzmaError, LzmaLibResult};
+use check::Check;
+use xz::FileInfo;
+
+
+/// The combined index of all the streams in an `.xz` file
+pub struct Index {
+	index: *mut lzma_index,
+}
+
+
+// lzma_index is only accessed through &self/&mut self, so it's safe to move between threads.
+unsafe impl Send for Index {}
+
+
+impl Index {
+	/// Reads the index of a complete `.xz` file held in memory.
+	///
+	/// Concatenated streams and stream padding are supported.
+	pub fn from_slice(buf: &[u8]) -> Result<Index, LzmaError> {
+		Index::from_info(&FileInfo::from_slice(buf)?)
+	}
+
+	/// Reads the index of a complete `.xz` file by seeking around in `reader`.
+	///
+	/// Concatenated streams and stream padding are supported.  The position of `reader` is left
+	/// unspecified.
+	pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Index, LzmaError> {
+		Index::from_info(&FileInfo::from_reader(reader)?)
+	}
+
+	/// Number of streams in the file
+	pub fn stream_count(&self) -> u64 {
+		unsafe { lzma_index_stream_count(self.index) }
+	}
+
+	/// Number of blocks in all streams
+	pub fn block_count(&self) -> u64 {
+		unsafe { lzma_index_block_count(self.index) }
+	}
+
+	/// Size of the file, including stream padding
+	pub fn file_size(&self) -> u64 {
+		unsafe { lzma_index_file_size(self.index) }
+	}
+
+	/// Size of the decompressed data
+	pub fn uncompressed_size(&self) -> u64 {
+		unsafe { lzma_index_uncompressed_size(self.index) }
+	}
+
+	/// The integrity checks used by the streams in the file.  Checks that liblzma doesn't know
+	/// about are left out.
+	pub fn checks(&self) -> Vec<Check> {
+		let mask = unsafe { lzma_index_checks(self.index) };
+
+		(0..16).filter(|id| mask & (1 << id) != 0).filter_map(Check::from_raw).collect()
+	}
+
+	/// Builds a liblzma index from the structure parsed by `FileInfo`
+	fn from_info(info: &FileInfo) -> Result<Index, LzmaError> {
+		let mut combined: Option<Index> = None;
+
+		for stream in info.streams() {
+			let mut index = Index {
+				index: unsafe { lzma_index_init(ptr::null()) },
+			};
+
+			if index.index.is_null() {
+				return Err(LzmaError::Mem);
+			}
+
+			let mut flags = lzma_stream_flags::new();
+			flags.check = stream.footer.check_id as u32;
+			flags.backward_size = stream.footer.backward_size;
+
+			unsafe {
+				for record in &stream.index.records {
+					LzmaLibResult::from(lzma_index_append(index.index, ptr::null(), record.unpadded_size, record.uncompressed_size)).map(|_| ())?;
+				}
+
+				LzmaLibResult::from(lzma_index_stream_flags(index.index, &flags)).map(|_| ())?;
+				LzmaLibResult::from(lzma_index_stream_padding(index.index, stream.padding)).map(|_| ())?;
+			}
+
+			combined = match combined {
+				Some(earlier) => {
+					unsafe {
+						LzmaLibResult::from(lzma_index_cat(earlier.index, index.index, ptr::null())).map(|_| ())?;
+					}
+
+					// lzma_index_cat frees src on success
+					index.index = ptr::null_mut();
+					Some(earlier)
+				},
+				None => Some(index),
+			};
+		}
+
+		combined.ok_or(LzmaError::Data)
+	}
+}
+
+
+impl Drop for Index {
+	fn drop(&mut self) {
+		// lzma_index_end does nothing if given NULL
+		unsafe {
+			lzma_index_end(self.index, ptr::null());
+		}
+	}
+}
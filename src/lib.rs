@@ -8,7 +8,9 @@
 //! and you can write data to a file while compressing it on the fly, or stream in an `xz` file
 //! from disk.
 //!
-//! `compress`/`decompress` are easy to use functions for simple use cases.
+//! `compress`/`decompress` are easy to use functions for simple use cases, and `verify` checks
+//! the integrity of `.xz` data without keeping the decompressed output.  `salvage` recovers the
+//! intact blocks of damaged `.xz` data.
 //!
 //! See the `LzmaReader` and `LzmaWriter` documentation for further details on that interface.
 //! `compress` and `decompress` are documented here.
@@ -29,16 +31,54 @@ pub enum Direction {
 	Decompress,
 }
 
+/// A container format for LZMA compressed data
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Format {
+	/// The `.xz` format
+	Xz,
+	/// The legacy `.lzma` format, also known as LZMA_Alone
+	Lzma,
+	/// The `.lz` format of the lzip tool.  Only decompression is supported, and asking for it
+	/// explicitly needs the `lzip` feature.
+	Lzip,
+	/// No container at all, just the output of the filter chain.  Nothing about how the data
+	/// was compressed is recorded, so the same filters must be used to decompress it.
+	Raw,
+}
+
 mod lzma_sys;
 mod lzma_stream_wrapper;
 pub mod reader;
 pub mod writer;
+pub mod threaded;
 pub mod error;
+pub mod check;
+pub mod filter;
+pub mod block;
+pub mod index;
+pub mod options;
+#[cfg(feature = "microlzma")]
+pub mod microlzma;
+pub mod parallel;
+pub mod xz;
+mod verify;
+mod salvage;
+mod capabilities;
+mod detect;
+mod cancel;
 
 use std::io::Read;
 pub use reader::LzmaReader;
 pub use writer::LzmaWriter;
+pub use threaded::ThreadedLzmaWriter;
 pub use error::LzmaError;
+pub use options::{EncoderOptions, DecoderOptions};
+pub use verify::{verify, verify_with_memlimit, VerifyReport};
+pub use salvage::{salvage, SalvageReport, LostRange};
+pub use capabilities::{capabilities, Capabilities};
+pub use detect::detect_format;
+pub use block::blocks;
+pub use cancel::CancellationToken;
 
 
 pub const EXTREME_PRESET: u32 = 1 << 31;
@@ -49,10 +89,29 @@ pub const EXTREME_PRESET: u32 = 1 << 31;
 /// preset is [0-9] and corresponds to xz's presets.
 /// Binary-or with EXTREME_PRESET for --extreme (e.g. 9 | EXTREME_PRESET).
 pub fn compress(buf: &[u8], preset: u32) -> Result<Vec<u8>, LzmaError> {
+	compress_with_options(buf, &EncoderOptions::new(preset))
+}
+
+
+/// Compress `buf` using the given options and return the result.
+///
+/// # Examples
+///
+/// ```
+/// use lzma::EncoderOptions;
+/// use std::time::{Duration, Instant};
+///
+/// // Give up if it takes more than a minute
+/// let options = EncoderOptions::new(6).deadline(Instant::now() + Duration::from_secs(60));
+/// let compressed = lzma::compress_with_options(b"Tears in rain", &options).unwrap();
+///
+/// assert_eq!(lzma::decompress(&compressed).unwrap(), b"Tears in rain");
+/// ```
+pub fn compress_with_options(buf: &[u8], options: &EncoderOptions) -> Result<Vec<u8>, LzmaError> {
 	let mut output: Vec<u8> = Vec::new();
 
 	{
-		let mut reader = LzmaReader::new_compressor(buf, preset)?;
+		let mut reader = LzmaReader::new_compressor_with_options(buf, options)?;
 
 		reader.read_to_end(&mut output)?;
 	}
@@ -63,10 +122,28 @@ pub fn compress(buf: &[u8], preset: u32) -> Result<Vec<u8>, LzmaError> {
 
 /// Decompress `buf` and return the result.
 pub fn decompress(buf: &[u8]) -> Result<Vec<u8>, LzmaError> {
+	decompress_with_options(buf, &DecoderOptions::new())
+}
+
+
+/// Decompress `buf` using the given options and return the result.
+///
+/// # Examples
+///
+/// ```
+/// use lzma::{DecoderOptions, LzmaError};
+///
+/// // Refuse to expand to more than 1 MiB
+/// let options = DecoderOptions::new().max_output(1024 * 1024);
+/// let compressed = lzma::compress(&vec![0u8; 2 * 1024 * 1024], 6).unwrap();
+///
+/// assert!(matches!(lzma::decompress_with_options(&compressed, &options), Err(LzmaError::OutputLimit)));
+/// ```
+pub fn decompress_with_options(buf: &[u8], options: &DecoderOptions) -> Result<Vec<u8>, LzmaError> {
 	let mut output: Vec<u8> = Vec::new();
 
 	{
-		let mut reader = LzmaReader::new_decompressor(buf)?;
+		let mut reader = LzmaReader::new_decompressor_with_options(buf, options)?;
 
 		reader.read_to_end(&mut output)?;
 	}
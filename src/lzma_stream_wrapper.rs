@@ -2,12 +2,60 @@
 
 use lzma_sys::*;
 use error::{LzmaError, LzmaLibResult};
+use block::RawBlock;
+use check::Check;
+use filter::{LzmaOptions, RawFilters};
+use options::TrailingData;
+use cancel::CancellationToken;
 use std::ptr;
+use std::time::Instant;
+use std::vec;
 use std::ops::Drop;
 
 
 pub struct LzmaStreamWrapper {
 	stream: lzma_stream,
+	// The block coders keep a pointer to the lzma_block they were initialized with, so it must
+	// live as long as the stream does.
+	block: Option<RawBlock>,
+	splitter: Option<BlockSplitter>,
+	check: Option<Check>,
+	max_output: Option<u64>,
+	max_ratio: Option<u64>,
+	trailing_data: TrailingData,
+	// Set once the end of the stream has been reached, unless trailing data is ignored
+	trailing: Option<Trailing>,
+	cancellation: Option<CancellationToken>,
+	deadline: Option<Instant>,
+}
+
+/// What has been seen after the end of the stream
+#[derive(Default)]
+struct Trailing {
+	len: u64,
+	/// Whether any of it was something other than null bytes
+	garbage: bool,
+}
+
+/// Tracks where new blocks should be started when encoding
+struct BlockSplitter {
+	block_size: Option<u64>,
+	block_list: vec::IntoIter<u64>,
+	/// Uncompressed bytes left in the current block, or None if it runs to the end of the stream
+	remaining: Option<u64>,
+	/// Whether an LZMA_FULL_FLUSH is in progress
+	flushing: bool,
+}
+
+impl BlockSplitter {
+	fn next_block(&mut self) {
+		self.remaining = match self.block_list.next() {
+			// The rest goes into one block, so there's no next one
+			Some(0) => None,
+			Some(size) => Some(size),
+			None => self.block_size,
+		};
+	}
 }
 
 pub struct LzmaCodeResult {
@@ -30,6 +78,15 @@ impl LzmaStreamWrapper {
 	pub fn new() -> LzmaStreamWrapper {
 		LzmaStreamWrapper {
 			stream: lzma_stream::new(),
+			block: None,
+			splitter: None,
+			check: None,
+			max_output: None,
+			max_ratio: None,
+			trailing_data: TrailingData::Ignore,
+			trailing: None,
+			cancellation: None,
+			deadline: None,
 		}
 	}
 
@@ -39,21 +96,313 @@ impl LzmaStreamWrapper {
 		}
 	}
 
-	pub fn stream_decoder(&mut self, memlimit: u64, flags: u32) -> Result<(), LzmaError> {
+	pub fn stream_encoder(&mut self, filters: &RawFilters, check: lzma_check) -> Result<(), LzmaError> {
+		unsafe {
+			LzmaLibResult::from(lzma_stream_encoder(&mut self.stream, filters.as_ptr(), check)).map(|_| ())
+		}
+	}
+
+	/// `filters` overrides `preset` if given.  A `block_size` of 0 lets liblzma choose.
+	pub fn stream_encoder_mt(&mut self, threads: u32, block_size: u64, preset: u32, filters: Option<&RawFilters>, check: lzma_check) -> Result<(), LzmaError> {
+		let mut options = lzma_mt::new();
+		options.threads = threads;
+		options.block_size = block_size;
+		options.preset = preset;
+		options.filters = filters.map_or(ptr::null(), |filters| filters.as_ptr());
+		options.check = check;
+
+		unsafe {
+			LzmaLibResult::from(lzma_stream_encoder_mt(&mut self.stream, &options)).map(|_| ())
+		}
+	}
+
+	pub fn alone_encoder(&mut self, options: &LzmaOptions) -> Result<(), LzmaError> {
+		let options = options.to_raw();
+
+		unsafe {
+			LzmaLibResult::from(lzma_alone_encoder(&mut self.stream, &options)).map(|_| ())
+		}
+	}
+
+	pub fn raw_encoder(&mut self, filters: &RawFilters) -> Result<(), LzmaError> {
+		unsafe {
+			LzmaLibResult::from(lzma_raw_encoder(&mut self.stream, filters.as_ptr())).map(|_| ())
+		}
+	}
+
+	/// Decodes .xz and legacy .lzma, detecting which one it is from the data
+	pub fn auto_decoder(&mut self, memlimit: u64, flags: u32) -> Result<(), LzmaError> {
 		unsafe {
 			LzmaLibResult::from(lzma_auto_decoder(&mut self.stream, memlimit, flags)).map(|_| ())
 		}
 	}
 
+	pub fn stream_decoder(&mut self, memlimit: u64, flags: u32) -> Result<(), LzmaError> {
+		unsafe {
+			LzmaLibResult::from(lzma_stream_decoder(&mut self.stream, memlimit, flags)).map(|_| ())
+		}
+	}
+
+	pub fn alone_decoder(&mut self, memlimit: u64) -> Result<(), LzmaError> {
+		// .lzma files never have a check, and liblzma won't report one
+		self.check = Some(Check::None);
+
+		unsafe {
+			LzmaLibResult::from(lzma_alone_decoder(&mut self.stream, memlimit)).map(|_| ())
+		}
+	}
+
+	#[cfg(feature = "lzip")]
+	pub fn lzip_decoder(&mut self, memlimit: u64, flags: u32) -> Result<(), LzmaError> {
+		unsafe {
+			LzmaLibResult::from(lzma_lzip_decoder(&mut self.stream, memlimit, flags)).map(|_| ())
+		}
+	}
+
+	pub fn raw_decoder(&mut self, filters: &RawFilters) -> Result<(), LzmaError> {
+		// Raw streams never have a check
+		self.check = Some(Check::None);
+
+		unsafe {
+			LzmaLibResult::from(lzma_raw_decoder(&mut self.stream, filters.as_ptr())).map(|_| ())
+		}
+	}
+
+	/// lzma_code must be called only once, with LZMA_FINISH, after initializing the encoder.
+	/// It encodes as much input as fits into the output.
+	#[cfg(feature = "microlzma")]
+	pub fn microlzma_encoder(&mut self, options: &LzmaOptions) -> Result<(), LzmaError> {
+		let options = options.to_raw();
+
+		unsafe {
+			LzmaLibResult::from(lzma_microlzma_encoder(&mut self.stream, &options)).map(|_| ())
+		}
+	}
+
+	#[cfg(feature = "microlzma")]
+	pub fn microlzma_decoder(&mut self, compressed_size: u64, uncompressed_size: u64, dict_size: u32) -> Result<(), LzmaError> {
+		// MicroLZMA has no check
+		self.check = Some(Check::None);
+
+		unsafe {
+			LzmaLibResult::from(lzma_microlzma_decoder(&mut self.stream, compressed_size, uncompressed_size, 1, dict_size)).map(|_| ())
+		}
+	}
+
+	pub fn block_encoder(&mut self, block: RawBlock) -> Result<(), LzmaError> {
+		let block = self.block.insert(block);
+
+		unsafe {
+			LzmaLibResult::from(lzma_block_encoder(&mut self.stream, block.as_mut_ptr())).map(|_| ())
+		}
+	}
+
+	pub fn block_decoder(&mut self, block: RawBlock) -> Result<(), LzmaError> {
+		let block = self.block.insert(block);
+
+		unsafe {
+			LzmaLibResult::from(lzma_block_decoder(&mut self.stream, block.as_mut_ptr())).map(|_| ())
+		}
+	}
+
+	/// The block given to `block_encoder` or `block_decoder`.  liblzma updates its sizes once
+	/// the block has been fully coded.
+	pub fn block(&self) -> Option<&RawBlock> {
+		self.block.as_ref()
+	}
+
+	/// Makes the encoder start a new block after every `block_size` bytes of input, or after each
+	/// of the sizes in `block_list` if given.
+	pub fn split_blocks(&mut self, block_size: Option<u64>, block_list: Vec<u64>) {
+		let mut splitter = BlockSplitter {
+			block_size,
+			block_list: block_list.into_iter(),
+			remaining: None,
+			flushing: false,
+		};
+		splitter.next_block();
+
+		self.splitter = Some(splitter);
+	}
+
+	/// Makes `code` fail with `LzmaError::OutputLimit` once more than `max_output` bytes have been
+	/// produced, or the output grows to more than `max_ratio` times the input consumed.
+	pub fn limit_output(&mut self, max_output: Option<u64>, max_ratio: Option<u64>) {
+		self.max_output = max_output;
+		self.max_ratio = max_ratio;
+	}
+
+	/// Makes `code` fail with `LzmaError::Cancelled`, without doing anything, once `cancellation`
+	/// has been cancelled or `deadline` has passed.
+	pub fn cancel_on(&mut self, cancellation: Option<CancellationToken>, deadline: Option<Instant>) {
+		self.cancellation = cancellation;
+		self.deadline = deadline;
+	}
+
+	/// Makes a decoder keep consuming input after the end of the stream, rather than stop there,
+	/// so that trailing data can be rejected or counted.  The end of the stream is then only
+	/// reported once `code` is called with `LZMA_FINISH`.
+	pub fn trailing_data(&mut self, trailing_data: TrailingData) {
+		self.trailing_data = trailing_data;
+	}
+
+	/// Number of bytes after the end of the stream that weren't stream padding, as far as
+	/// they have been seen
+	pub fn trailing_bytes(&self) -> u64 {
+		match self.trailing {
+			Some(ref trailing) if trailing.garbage || trailing.len % 4 != 0 => trailing.len,
+			_ => 0,
+		}
+	}
+
+	/// The integrity check of the stream being decoded.  Known once liblzma has reported it with
+	/// LZMA_GET_CHECK (which requires the LZMA_TELL_ANY_CHECK flag), and None if the check is
+	/// one liblzma doesn't know about.
+	pub fn check(&self) -> Option<Check> {
+		self.check
+	}
+
 	pub fn end(&mut self) {
 		unsafe {
 			lzma_end(&mut self.stream)
 		}
 	}
 
+	/// Runs liblzma on `input`, writing to `output`.
+	///
+	/// If `split_blocks` was called, the input is cut at block boundaries and an
+	/// `LZMA_FULL_FLUSH` is done at each of them.
+	pub fn code(&mut self, input: &[u8], output: &mut [u8], action: lzma_action) -> LzmaCodeResult {
+		let cancelled = self.cancellation.as_ref().is_some_and(CancellationToken::is_cancelled);
+		let expired = self.deadline.is_some_and(|deadline| Instant::now() >= deadline);
+
+		if cancelled || expired {
+			return LzmaCodeResult {
+				ret: Err(LzmaError::Cancelled),
+				bytes_read: 0,
+				bytes_written: 0,
+			};
+		}
+
+		if self.trailing.is_some() {
+			return self.code_trailing(input, action);
+		}
+
+		// Only leave room for one byte past the limit; that's enough to tell it was exceeded
+		let output = match self.max_output {
+			Some(max_output) => {
+				let room = max_output.saturating_sub(self.stream.total_out).saturating_add(1);
+				let len = output.len().min(room.min(usize::MAX as u64) as usize);
+				&mut output[..len]
+			},
+			None => output,
+		};
+
+		let mut result = match self.splitter.take() {
+			Some(mut splitter) => {
+				let result = self.code_split(&mut splitter, input, output, action);
+				self.splitter = Some(splitter);
+				result
+			},
+			None => self.code_raw(input, output, action),
+		};
+
+		let over_size = self.max_output.is_some_and(|max_output| self.stream.total_out > max_output);
+		let over_ratio = self.max_ratio.is_some_and(|max_ratio| self.stream.total_out > self.stream.total_in.saturating_mul(max_ratio));
+
+		if over_size || over_ratio {
+			result.ret = Err(LzmaError::OutputLimit);
+		}
+
+		// liblzma stops at the end of the stream; what follows is up to the trailing data policy
+		if let (Ok(lzma_ret::LzmaStreamEnd), false) = (&result.ret, self.trailing_data == TrailingData::Ignore) {
+			self.trailing = Some(Trailing::default());
+
+			let rest = self.code_trailing(&input[result.bytes_read..], action);
+			result.bytes_read += rest.bytes_read;
+			result.ret = rest.ret;
+		}
+
+		result
+	}
+
+	/// Consumes input after the end of the stream.  Only null bytes, in multiples of four, are
+	/// accepted by `TrailingData::Reject`, like the stream padding of `.xz` files.
+	fn code_trailing(&mut self, input: &[u8], action: lzma_action) -> LzmaCodeResult {
+		let trailing = self.trailing.as_mut().unwrap();
+		trailing.len += input.len() as u64;
+		trailing.garbage |= input.iter().any(|&b| b != 0);
+
+		let finishing = matches!(action, lzma_action::LzmaFinish);
+		let rejected = trailing.garbage || (finishing && trailing.len % 4 != 0);
+
+		let ret = if rejected && self.trailing_data == TrailingData::Reject {
+			Err(LzmaError::TrailingData)
+		} else if finishing {
+			Ok(lzma_ret::LzmaStreamEnd)
+		} else {
+			Ok(lzma_ret::LzmaOk)
+		};
+
+		LzmaCodeResult {
+			ret,
+			bytes_read: input.len(),
+			bytes_written: 0,
+		}
+	}
+
+	fn code_split(&mut self, splitter: &mut BlockSplitter, input: &[u8], output: &mut [u8], action: lzma_action) -> LzmaCodeResult {
+		let mut bytes_written = 0;
+
+		if splitter.flushing {
+			let result = self.code_raw(&[], output, lzma_action::LzmaFullFlush);
+			bytes_written = result.bytes_written;
+
+			match result.ret {
+				Ok(lzma_ret::LzmaStreamEnd) => {
+					splitter.flushing = false;
+					splitter.next_block();
+				},
+				_ => return result,
+			}
+
+			// The flush is done, so carry on with the input if there's anywhere to put the output.
+			// Running again with nothing to do would make liblzma report LZMA_BUF_ERROR.
+			let finishing = matches!(action, lzma_action::LzmaFinish);
+
+			if bytes_written == output.len() || (input.is_empty() && !finishing) {
+				return LzmaCodeResult {
+					ret: Ok(lzma_ret::LzmaOk),
+					bytes_read: 0,
+					bytes_written,
+				};
+			}
+		}
+
+		// Don't let liblzma read past the end of the current block
+		let (input, action) = match splitter.remaining {
+			Some(remaining) if remaining < input.len() as u64 => (&input[..(remaining as usize)], lzma_action::LzmaRun),
+			_ => (input, action),
+		};
+		let running = matches!(action, lzma_action::LzmaRun);
+
+		let mut result = self.code_raw(input, &mut output[bytes_written..], action);
+		result.bytes_written += bytes_written;
+
+		if let Some(ref mut remaining) = splitter.remaining {
+			*remaining -= result.bytes_read as u64;
+
+			if *remaining == 0 && running && result.ret.is_ok() {
+				splitter.flushing = true;
+			}
+		}
+
+		result
+	}
+
 	/// Pointers to input and output are given to liblzma during execution of this function,
 	/// but they are removed before returning.  So that should keep everything safe.
-	pub fn code(&mut self, input: &[u8], output: &mut [u8], action: lzma_action) -> LzmaCodeResult {
+	fn code_raw(&mut self, input: &[u8], output: &mut [u8], action: lzma_action) -> LzmaCodeResult {
 		// Prepare lzma_stream
 		self.stream.next_in = input.as_ptr();
 		self.stream.avail_in = input.len();
@@ -67,6 +416,11 @@ impl LzmaStreamWrapper {
 		let bytes_read = input.len() - self.stream.avail_in;
 		let bytes_written = output.len() - self.stream.avail_out;
 
+		// These are only informational; decoding carries on as normal
+		if let Ok(lzma_ret::LzmaGetCheck) | Ok(lzma_ret::LzmaNoCheck) = ret {
+			self.check = Check::from_raw(unsafe { lzma_get_check(&self.stream) });
+		}
+
 		// Clear pointers from lzma_stream
 		self.stream.next_in = ptr::null();
 		self.stream.avail_in = 0;
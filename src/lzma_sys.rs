@@ -1,4 +1,6 @@
-use std::os::raw::c_void;
+use std::os::raw::{c_char, c_void};
+#[cfg(feature = "filter-strings")]
+use std::os::raw::c_int;
 use std::mem;
 
 
@@ -53,8 +55,8 @@ impl lzma_stream {
 
 #[repr(C)]
 pub struct lzma_allocator {
-	pub alloc: *mut extern fn(opaque: *mut c_void, nmemb: usize, size: usize),
-	pub free: extern fn(opaque: *mut c_void, ptr: *mut c_void),
+	pub alloc: extern "C" fn(opaque: *mut c_void, nmemb: usize, size: usize) -> *mut c_void,
+	pub free: extern "C" fn(opaque: *mut c_void, ptr: *mut c_void),
 	pub opaque: *mut c_void,
 }
 
@@ -103,9 +105,319 @@ pub enum lzma_check {
 }
 
 
-extern {
+#[allow(non_camel_case_types)]
+pub type lzma_vli = u64;
+#[allow(non_camel_case_types)]
+pub type lzma_bool = u8;
+
+// vli.h defines LZMA_VLI_UNKNOWN as UINT64_MAX.
+pub const LZMA_VLI_UNKNOWN: lzma_vli = u64::MAX;
+pub const LZMA_CHECK_SIZE_MAX: usize = 64;
+pub const LZMA_FILTERS_MAX: usize = 4;
+pub const LZMA_BLOCK_HEADER_SIZE_MAX: usize = 1024;
+pub const LZMA_THREADS_MAX: u32 = 16384;
+
+pub const LZMA_FILTER_LZMA1: lzma_vli = 0x4000000000000001;
+pub const LZMA_FILTER_LZMA2: lzma_vli = 0x21;
+pub const LZMA_FILTER_DELTA: lzma_vli = 0x03;
+pub const LZMA_FILTER_X86: lzma_vli = 0x04;
+pub const LZMA_FILTER_POWERPC: lzma_vli = 0x05;
+pub const LZMA_FILTER_IA64: lzma_vli = 0x06;
+pub const LZMA_FILTER_ARM: lzma_vli = 0x07;
+pub const LZMA_FILTER_ARMTHUMB: lzma_vli = 0x08;
+pub const LZMA_FILTER_SPARC: lzma_vli = 0x09;
+pub const LZMA_FILTER_ARM64: lzma_vli = 0x0A;
+
+
+#[repr(C)]
+pub struct lzma_filter {
+	pub id: lzma_vli,
+	pub options: *mut c_void,
+}
+
+
+// mode and mf are lzma_mode and lzma_match_finder in lzma12.h.  They're plain u32 here because
+// liblzma's property decoders leave them uninitialized, so they can hold any value.
+#[repr(C)]
+pub struct lzma_options_lzma {
+	pub dict_size: u32,
+	pub preset_dict: *const u8,
+	pub preset_dict_size: u32,
+	pub lc: u32,
+	pub lp: u32,
+	pub pb: u32,
+	pub mode: u32,
+	pub nice_len: u32,
+	pub mf: u32,
+	pub depth: u32,
+	pub ext_flags: u32,
+	pub ext_size_low: u32,
+	pub ext_size_high: u32,
+	pub reserved_int4: u32,
+	pub reserved_int5: u32,
+	pub reserved_int6: u32,
+	pub reserved_int7: u32,
+	pub reserved_int8: u32,
+	pub reserved_enum1: u32,
+	pub reserved_enum2: u32,
+	pub reserved_enum3: u32,
+	pub reserved_enum4: u32,
+	pub reserved_ptr1: *mut c_void,
+	pub reserved_ptr2: *mut c_void,
+}
+
+impl lzma_options_lzma {
+	pub fn new() -> lzma_options_lzma {
+		unsafe {
+			mem::zeroed()
+		}
+	}
+}
+
+
+#[repr(C)]
+pub struct lzma_options_bcj {
+	pub start_offset: u32,
+}
+
+
+#[repr(C)]
+pub struct lzma_options_delta {
+	pub type_: u32,    // Actually lzma_delta_type, which only has LZMA_DELTA_TYPE_BYTE (0)
+	pub dist: u32,
+	pub reserved_int1: u32,
+	pub reserved_int2: u32,
+	pub reserved_int3: u32,
+	pub reserved_int4: u32,
+	pub reserved_ptr1: *mut c_void,
+	pub reserved_ptr2: *mut c_void,
+}
+
+impl lzma_options_delta {
+	pub fn new() -> lzma_options_delta {
+		unsafe {
+			mem::zeroed()
+		}
+	}
+}
+
+
+#[repr(C)]
+pub struct lzma_block {
+	pub version: u32,
+	pub header_size: u32,
+	pub check: lzma_check,
+	pub compressed_size: lzma_vli,
+	pub uncompressed_size: lzma_vli,
+	pub filters: *mut lzma_filter,
+	pub raw_check: [u8; LZMA_CHECK_SIZE_MAX],
+	pub reserved_ptr1: *mut c_void,
+	pub reserved_ptr2: *mut c_void,
+	pub reserved_ptr3: *mut c_void,
+	pub reserved_int1: u32,
+	pub reserved_int2: u32,
+	pub reserved_int3: lzma_vli,
+	pub reserved_int4: lzma_vli,
+	pub reserved_int5: lzma_vli,
+	pub reserved_int6: lzma_vli,
+	pub reserved_int7: lzma_vli,
+	pub reserved_int8: lzma_vli,
+	pub reserved_enum1: u32,
+	pub reserved_enum2: u32,
+	pub reserved_enum3: u32,
+	pub reserved_enum4: u32,
+	pub ignore_check: lzma_bool,
+	pub reserved_bool2: lzma_bool,
+	pub reserved_bool3: lzma_bool,
+	pub reserved_bool4: lzma_bool,
+	pub reserved_bool5: lzma_bool,
+	pub reserved_bool6: lzma_bool,
+	pub reserved_bool7: lzma_bool,
+	pub reserved_bool8: lzma_bool,
+}
+
+impl lzma_block {
+	pub fn new() -> lzma_block {
+		unsafe {
+			mem::zeroed()
+		}
+	}
+}
+
+
+pub const LZMA_STREAM_HEADER_SIZE: usize = 12;
+
+// Decoder flags
+pub const LZMA_TELL_ANY_CHECK: u32 = 0x04;
+pub const LZMA_CONCATENATED: u32 = 0x08;
+pub const LZMA_IGNORE_CHECK: u32 = 0x10;
+
+// Flags for lzma_str_to_filters and lzma_str_from_filters
+#[cfg(feature = "filter-strings")]
+pub const LZMA_STR_ALL_FILTERS: u32 = 0x01;
+#[cfg(feature = "filter-strings")]
+pub const LZMA_STR_ENCODER: u32 = 0x10;
+
+
+#[repr(C)]
+pub struct lzma_stream_flags {
+	pub version: u32,
+	pub backward_size: lzma_vli,
+	pub check: u32,    // Actually lzma_check, but decoded headers can hold check IDs it doesn't define
+	pub reserved_enum1: u32,
+	pub reserved_enum2: u32,
+	pub reserved_enum3: u32,
+	pub reserved_enum4: u32,
+	pub reserved_bool1: lzma_bool,
+	pub reserved_bool2: lzma_bool,
+	pub reserved_bool3: lzma_bool,
+	pub reserved_bool4: lzma_bool,
+	pub reserved_bool5: lzma_bool,
+	pub reserved_bool6: lzma_bool,
+	pub reserved_bool7: lzma_bool,
+	pub reserved_bool8: lzma_bool,
+	pub reserved_int1: u32,
+	pub reserved_int2: u32,
+}
+
+impl lzma_stream_flags {
+	pub fn new() -> lzma_stream_flags {
+		unsafe {
+			mem::zeroed()
+		}
+	}
+}
+
+
+#[repr(C)]
+pub struct lzma_mt {
+	pub flags: u32,
+	pub threads: u32,
+	pub block_size: u64,
+	pub timeout: u32,
+	pub preset: u32,
+	pub filters: *const lzma_filter,
+	pub check: lzma_check,
+	pub reserved_enum1: u32,
+	pub reserved_enum2: u32,
+	pub reserved_enum3: u32,
+	pub reserved_int1: u32,
+	pub reserved_int2: u32,
+	pub reserved_int3: u32,
+	pub reserved_int4: u32,
+	pub memlimit_threading: u64,
+	pub memlimit_stop: u64,
+	pub reserved_int7: u64,
+	pub reserved_int8: u64,
+	pub reserved_ptr1: *mut c_void,
+	pub reserved_ptr2: *mut c_void,
+	pub reserved_ptr3: *mut c_void,
+	pub reserved_ptr4: *mut c_void,
+}
+
+impl lzma_mt {
+	pub fn new() -> lzma_mt {
+		unsafe {
+			mem::zeroed()
+		}
+	}
+}
+
+
+// Opaque
+#[repr(C)]
+pub struct lzma_index {
+	_private: [u8; 0],
+}
+
+// Opaque
+#[repr(C)]
+pub struct lzma_index_hash {
+	_private: [u8; 0],
+}
+
+
+extern "C" {
 	pub fn lzma_easy_encoder(stream: *mut lzma_stream, preset: u32, check: lzma_check) -> lzma_ret;
 	pub fn lzma_code(stream: *mut lzma_stream, action: lzma_action) -> lzma_ret;
 	pub fn lzma_end(stream: *mut lzma_stream);
 	pub fn lzma_auto_decoder(stream: *mut lzma_stream, memlimit: u64, flags: u32) -> lzma_ret;
+	pub fn lzma_stream_encoder(stream: *mut lzma_stream, filters: *const lzma_filter, check: lzma_check) -> lzma_ret;
+	pub fn lzma_stream_encoder_mt(stream: *mut lzma_stream, options: *const lzma_mt) -> lzma_ret;
+	pub fn lzma_stream_decoder(stream: *mut lzma_stream, memlimit: u64, flags: u32) -> lzma_ret;
+	pub fn lzma_alone_encoder(stream: *mut lzma_stream, options: *const lzma_options_lzma) -> lzma_ret;
+	#[cfg(feature = "lzip")]
+	pub fn lzma_lzip_decoder(stream: *mut lzma_stream, memlimit: u64, flags: u32) -> lzma_ret;
+	pub fn lzma_alone_decoder(stream: *mut lzma_stream, memlimit: u64) -> lzma_ret;
+	pub fn lzma_raw_encoder(stream: *mut lzma_stream, filters: *const lzma_filter) -> lzma_ret;
+	pub fn lzma_raw_decoder(stream: *mut lzma_stream, filters: *const lzma_filter) -> lzma_ret;
+	#[cfg(feature = "microlzma")]
+	pub fn lzma_microlzma_encoder(stream: *mut lzma_stream, options: *const lzma_options_lzma) -> lzma_ret;
+	#[cfg(feature = "microlzma")]
+	pub fn lzma_microlzma_decoder(stream: *mut lzma_stream, comp_size: u64, uncomp_size: u64, uncomp_size_is_exact: lzma_bool, dict_size: u32) -> lzma_ret;
+	pub fn lzma_get_check(stream: *const lzma_stream) -> u32;    // Actually returns lzma_check, but may be an ID it doesn't define
+
+	pub fn lzma_easy_encoder_memusage(preset: u32) -> u64;
+	pub fn lzma_easy_decoder_memusage(preset: u32) -> u64;
+	pub fn lzma_raw_decoder_memusage(filters: *const lzma_filter) -> u64;
+	pub fn lzma_cputhreads() -> u32;
+
+	pub fn lzma_crc32(buf: *const u8, size: usize, crc: u32) -> u32;
+	pub fn lzma_crc64(buf: *const u8, size: usize, crc: u64) -> u64;
+
+	pub fn lzma_version_number() -> u32;
+	pub fn lzma_version_string() -> *const c_char;
+	pub fn lzma_check_is_supported(check: lzma_check) -> lzma_bool;
+	pub fn lzma_filter_encoder_is_supported(id: lzma_vli) -> lzma_bool;
+	pub fn lzma_filter_decoder_is_supported(id: lzma_vli) -> lzma_bool;
+	pub fn lzma_mf_is_supported(mf: u32) -> lzma_bool;
+	pub fn lzma_mode_is_supported(mode: u32) -> lzma_bool;
+
+	pub fn lzma_lzma_preset(options: *mut lzma_options_lzma, preset: u32) -> lzma_bool;
+	pub fn lzma_properties_size(size: *mut u32, filter: *const lzma_filter) -> lzma_ret;
+	pub fn lzma_properties_encode(filter: *const lzma_filter, props: *mut u8) -> lzma_ret;
+	pub fn lzma_properties_decode(filter: *mut lzma_filter, allocator: *const lzma_allocator, props: *const u8, props_size: usize) -> lzma_ret;
+	pub fn lzma_filter_flags_size(size: *mut u32, filter: *const lzma_filter) -> lzma_ret;
+	pub fn lzma_filter_flags_encode(filter: *const lzma_filter, out: *mut u8, out_pos: *mut usize, out_size: usize) -> lzma_ret;
+	pub fn lzma_filter_flags_decode(filter: *mut lzma_filter, allocator: *const lzma_allocator, input: *const u8, in_pos: *mut usize, in_size: usize) -> lzma_ret;
+	#[cfg(feature = "filter-strings")]
+	pub fn lzma_str_to_filters(s: *const c_char, error_pos: *mut c_int, filters: *mut lzma_filter, flags: u32, allocator: *const lzma_allocator) -> *const c_char;
+	#[cfg(feature = "filter-strings")]
+	pub fn lzma_str_from_filters(s: *mut *mut c_char, filters: *const lzma_filter, flags: u32, allocator: *const lzma_allocator) -> lzma_ret;
+
+	pub fn lzma_block_header_size(block: *mut lzma_block) -> lzma_ret;
+	pub fn lzma_block_header_encode(block: *const lzma_block, out: *mut u8) -> lzma_ret;
+	pub fn lzma_block_header_decode(block: *mut lzma_block, allocator: *const lzma_allocator, input: *const u8) -> lzma_ret;
+	pub fn lzma_block_compressed_size(block: *mut lzma_block, unpadded_size: lzma_vli) -> lzma_ret;
+	pub fn lzma_block_unpadded_size(block: *const lzma_block) -> lzma_vli;
+	pub fn lzma_block_total_size(block: *const lzma_block) -> lzma_vli;
+	pub fn lzma_block_encoder(stream: *mut lzma_stream, block: *mut lzma_block) -> lzma_ret;
+	pub fn lzma_block_decoder(stream: *mut lzma_stream, block: *mut lzma_block) -> lzma_ret;
+
+	pub fn lzma_stream_header_encode(options: *const lzma_stream_flags, out: *mut u8) -> lzma_ret;
+	pub fn lzma_stream_footer_encode(options: *const lzma_stream_flags, out: *mut u8) -> lzma_ret;
+	pub fn lzma_stream_header_decode(options: *mut lzma_stream_flags, input: *const u8) -> lzma_ret;
+	pub fn lzma_stream_footer_decode(options: *mut lzma_stream_flags, input: *const u8) -> lzma_ret;
+	pub fn lzma_stream_flags_compare(a: *const lzma_stream_flags, b: *const lzma_stream_flags) -> lzma_ret;
+
+	pub fn lzma_index_init(allocator: *const lzma_allocator) -> *mut lzma_index;
+	pub fn lzma_index_append(i: *mut lzma_index, allocator: *const lzma_allocator, unpadded_size: lzma_vli, uncompressed_size: lzma_vli) -> lzma_ret;
+	pub fn lzma_index_size(i: *const lzma_index) -> lzma_vli;
+	pub fn lzma_index_buffer_encode(i: *const lzma_index, out: *mut u8, out_pos: *mut usize, out_size: usize) -> lzma_ret;
+	pub fn lzma_index_end(i: *mut lzma_index, allocator: *const lzma_allocator);
+	pub fn lzma_index_cat(dest: *mut lzma_index, src: *mut lzma_index, allocator: *const lzma_allocator) -> lzma_ret;
+	pub fn lzma_index_stream_flags(i: *mut lzma_index, stream_flags: *const lzma_stream_flags) -> lzma_ret;
+	pub fn lzma_index_stream_padding(i: *mut lzma_index, stream_padding: lzma_vli) -> lzma_ret;
+	pub fn lzma_index_checks(i: *const lzma_index) -> u32;
+	pub fn lzma_index_stream_count(i: *const lzma_index) -> lzma_vli;
+	pub fn lzma_index_block_count(i: *const lzma_index) -> lzma_vli;
+	pub fn lzma_index_file_size(i: *const lzma_index) -> lzma_vli;
+	pub fn lzma_index_uncompressed_size(i: *const lzma_index) -> lzma_vli;
+
+	pub fn lzma_index_hash_init(index_hash: *mut lzma_index_hash, allocator: *const lzma_allocator) -> *mut lzma_index_hash;
+	pub fn lzma_index_hash_end(index_hash: *mut lzma_index_hash, allocator: *const lzma_allocator);
+	pub fn lzma_index_hash_append(index_hash: *mut lzma_index_hash, unpadded_size: lzma_vli, uncompressed_size: lzma_vli) -> lzma_ret;
+	pub fn lzma_index_hash_decode(index_hash: *mut lzma_index_hash, input: *const u8, in_pos: *mut usize, in_size: usize) -> lzma_ret;
+	pub fn lzma_index_hash_size(index_hash: *const lzma_index_hash) -> lzma_vli;
 }
+
@@ -0,0 +1,115 @@
+//! This module implements the MicroLZMA format, as used by the EROFS file system.
+//!
+//! MicroLZMA is raw LZMA1 data with a tiny header.  It records neither the compressed nor the
+//! uncompressed size, so both have to be stored elsewhere and given to the decoder.
+//!
+//! Rather than compressing all of its input, the encoder compresses as much of it as fits into
+//! a fixed amount of output, which makes it easy to produce compressed chunks of a fixed size.
+//!
+//! This module needs the `microlzma` feature, and liblzma 5.4 or later.
+//!
+//!
+//! # Examples
+//!
+//! ```
+//! use lzma::filter::LzmaOptions;
+//! use lzma::microlzma::{self, MicroLzmaEncoder};
+//!
+//! let data = b"All those moments will be lost in time, like tears in rain.".repeat(1000);
+//! let mut options = LzmaOptions::preset(6).unwrap();
+//! options.dict_size = 64 * 1024;
+//!
+//! // Fill a 4 KiB block with as much of the data as fits
+//! let mut encoder = MicroLzmaEncoder::new(&options).unwrap();
+//! let mut block = [0u8; 4096];
+//! let (consumed, written) = encoder.encode(&data, &mut block).unwrap();
+//!
+//! let decompressed = microlzma::decompress(&block[..written], consumed as u64, options.dict_size).unwrap();
+//! assert_eq!(decompressed, &data[..consumed]);
+//! ```
+
+use std::io::Read;
+use lzma_sys::*;
+use error::LzmaError;
+use filter::{Filter, LzmaOptions};
+use lzma_stream_wrapper::LzmaStreamWrapper;
+use reader::LzmaReader;
+use capabilities;
+
+
+const DEFAULT_BUF_SIZE: usize = 4 * 1024;
+
+
+/// Compresses chunks of data into MicroLZMA streams.
+///
+/// The encoder can be reused for any number of chunks, which saves liblzma from allocating
+/// its state again for each one.
+pub struct MicroLzmaEncoder {
+	stream: LzmaStreamWrapper,
+	options: LzmaOptions,
+}
+
+impl MicroLzmaEncoder {
+	/// An encoder using the given LZMA options.
+	///
+	/// Since chunks are small, a small dictionary keeps the encoder fast.  liblzma suggests
+	/// 32 to 64 KiB for 4 KiB of output.
+	pub fn new(options: &LzmaOptions) -> Result<MicroLzmaEncoder, LzmaError> {
+		capabilities::require_encoder(&[Filter::Lzma1(options.clone())])?;
+
+		Ok(MicroLzmaEncoder {
+			stream: LzmaStreamWrapper::new(),
+			options: options.clone(),
+		})
+	}
+
+	/// Compresses as much of `input` as fits into `output`, as a complete MicroLZMA stream.
+	///
+	/// Returns the number of bytes of `input` that were compressed and the number of bytes
+	/// written to `output`.  Both need to be stored, since they are required for decompression.
+	/// `output` isn't always filled completely, even if not all of `input` fit.
+	///
+	/// `output` must be at least 6 bytes long; `LzmaError::Options` is returned otherwise.
+	pub fn encode(&mut self, input: &[u8], output: &mut [u8]) -> Result<(usize, usize), LzmaError> {
+		if output.len() < 6 {
+			return Err(LzmaError::Options);
+		}
+
+		self.stream.microlzma_encoder(&self.options)?;
+
+		let result = self.stream.code(input, output, lzma_action::LzmaFinish);
+
+		match result.ret? {
+			lzma_ret::LzmaStreamEnd => Ok((result.bytes_read, result.bytes_written)),
+			_ => Err(LzmaError::Other),
+		}
+	}
+}
+
+
+/// Creates a reader that decompresses the MicroLZMA stream read from `inner`.
+///
+/// `compressed_size` and `uncompressed_size` must be exactly the sizes returned by
+/// `MicroLzmaEncoder::encode`.  `dict_size` is the dictionary size used when compressing; a
+/// bigger value works too, but uses more memory.
+pub fn decoder<R: Read>(inner: R, compressed_size: u64, uncompressed_size: u64, dict_size: u32) -> Result<LzmaReader<R>, LzmaError> {
+	let mut stream = LzmaStreamWrapper::new();
+	stream.microlzma_decoder(compressed_size, uncompressed_size, dict_size)?;
+
+	Ok(LzmaReader::from_stream(DEFAULT_BUF_SIZE, inner, stream))
+}
+
+
+/// Decompresses the MicroLZMA stream in `buf`.  See `decoder`.
+pub fn decompress(buf: &[u8], uncompressed_size: u64, dict_size: u32) -> Result<Vec<u8>, LzmaError> {
+	// uncompressed_size comes from the caller and may be wrong, so it is not used to preallocate
+	let mut output = Vec::new();
+
+	{
+		let mut reader = decoder(buf, buf.len() as u64, uncompressed_size, dict_size)?;
+
+		reader.read_to_end(&mut output)?;
+	}
+
+	Ok(output)
+}
@@ -0,0 +1,516 @@
+//! Options for creating compressors and decompressors.
+//!
+//! The `new_compressor`/`new_decompressor` constructors of `LzmaReader` and `LzmaWriter` cover
+//! the common cases.  The `_with_options` variants take one of the types here instead, for when
+//! more control is needed.
+
+use lzma_sys::*;
+use error::LzmaError;
+use check::Check;
+use filter::{Filter, FilterChain, LzmaOptions};
+use lzma_stream_wrapper::LzmaStreamWrapper;
+use capabilities;
+use cancel::CancellationToken;
+use std::time::Instant;
+use ::EXTREME_PRESET;
+use ::Format;
+
+
+/// Options for compressing data.
+///
+/// # Examples
+///
+/// ```
+/// use lzma::{EncoderOptions, LzmaWriter};
+/// use std::io::prelude::*;
+///
+/// // Start a new block for every MiB of input, like xz --block-size=1MiB
+/// let options = EncoderOptions::new(6).block_size(1024 * 1024);
+/// let mut f = LzmaWriter::new_compressor_with_options(Vec::new(), &options).unwrap();
+///
+/// write!(f, "It's a small world!").unwrap();
+/// f.finish().unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncoderOptions {
+	preset: u32,
+	check: Check,
+	format: Format,
+	filters: Option<FilterChain>,
+	threads: u32,
+	block_size: Option<u64>,
+	block_list: Vec<u64>,
+	cancellation: Option<CancellationToken>,
+	deadline: Option<Instant>,
+}
+
+impl EncoderOptions {
+	/// Options producing an `.xz` stream using the given preset and a CRC64 check.
+	///
+	/// preset is [0-9] and corresponds to xz's presets.
+	/// Binary-or with EXTREME_PRESET for --extreme (e.g. 9 | EXTREME_PRESET).
+	pub fn new(preset: u32) -> EncoderOptions {
+		EncoderOptions {
+			preset,
+			check: Check::Crc64,
+			format: Format::Xz,
+			filters: None,
+			threads: 1,
+			block_size: None,
+			block_list: Vec::new(),
+			cancellation: None,
+			deadline: None,
+		}
+	}
+
+	/// Options using the highest preset whose encoder fits in `memory_budget` bytes of memory,
+	/// and whose output can be decompressed within `decoder_memory_budget` bytes.
+	///
+	/// Higher presets are preferred, and at each level the extreme variant is preferred when it
+	/// fits, since it compresses a little better.  Extreme presets are considerably slower, so
+	/// clear `EXTREME_PRESET` from `preset()` if that matters more than size.  Returns
+	/// `LzmaError::MemLimit` if not even preset 0 fits.
+	///
+	/// Memory usage is that of the single-threaded encoder; see liblzma's
+	/// `lzma_easy_encoder_memusage`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use lzma::EncoderOptions;
+	///
+	/// let options = EncoderOptions::best_for(128 * 1024 * 1024, 16 * 1024 * 1024).unwrap();
+	/// println!("Compressing with preset {}", options.preset() & !lzma::EXTREME_PRESET);
+	/// ```
+	pub fn best_for(memory_budget: u64, decoder_memory_budget: u64) -> Result<EncoderOptions, LzmaError> {
+		for level in (0..=9).rev() {
+			for &preset in &[level | EXTREME_PRESET, level] {
+				let (encoder, decoder) = unsafe {
+					(lzma_easy_encoder_memusage(preset), lzma_easy_decoder_memusage(preset))
+				};
+
+				// liblzma returns UINT64_MAX for unsupported presets
+				if encoder <= memory_budget && decoder <= decoder_memory_budget && encoder != u64::MAX {
+					return Ok(EncoderOptions::new(preset));
+				}
+			}
+		}
+
+		Err(LzmaError::MemLimit)
+	}
+
+	/// The preset, including `EXTREME_PRESET` if set
+	pub fn preset(&self) -> u32 {
+		self.preset
+	}
+
+	/// The integrity check stored in the stream.  Only used by `Format::Xz`.
+	pub fn check(mut self, check: Check) -> EncoderOptions {
+		self.check = check;
+		self
+	}
+
+	/// The container format to produce (default `Format::Xz`)
+	pub fn format(mut self, format: Format) -> EncoderOptions {
+		self.format = format;
+		self
+	}
+
+	pub(crate) fn output_format(&self) -> Format {
+		self.format
+	}
+
+	/// Use a custom filter chain instead of the preset.
+	///
+	/// For `Format::Lzma` the chain must be a single LZMA1 filter.
+	pub fn filters(mut self, filters: FilterChain) -> EncoderOptions {
+		self.filters = Some(filters);
+		self
+	}
+
+	/// Compress using liblzma's multithreaded encoder, like xz's `--threads`.  0 means one
+	/// thread per CPU core.  Only used by `Format::Xz`.
+	///
+	/// The multithreaded encoder splits the data into blocks so that they can be compressed in
+	/// parallel.  If `block_size` isn't set, liblzma picks a block size based on the preset.
+	///
+	/// More than `LZMA_THREADS_MAX` (16384) threads is rejected with `LzmaError::Options`.
+	pub fn threads(mut self, threads: u32) -> EncoderOptions {
+		self.threads = threads;
+		self
+	}
+
+	/// Start a new block every `size` bytes of uncompressed data, like xz's `--block-size`.
+	/// Only used by `Format::Xz`.
+	///
+	/// By default everything is compressed into a single block.  Smaller blocks can be
+	/// decompressed independently of each other, at the cost of a slightly worse ratio.
+	pub fn block_size(mut self, size: u64) -> EncoderOptions {
+		self.block_size = Some(size);
+		self
+	}
+
+	/// Start new blocks after the given amounts of uncompressed data, like xz's `--block-list`.
+	/// Only used by `Format::Xz`.
+	///
+	/// Once the list runs out, `block_size` is used if set; otherwise the last block continues
+	/// until the end of the data.  As in xz, a 0 at the end of the list puts the rest of the data
+	/// into a single block regardless of `block_size`, except with threads, where liblzma still
+	/// applies it.  A 0 anywhere else fails with `LzmaError::Options`.
+	pub fn block_list(mut self, sizes: &[u64]) -> EncoderOptions {
+		self.block_list = sizes.to_vec();
+		self
+	}
+
+	/// Stop with `LzmaError::Cancelled` once `token` is cancelled.  See `CancellationToken`.
+	///
+	/// The inner reader or writer can still be recovered with `into_inner` afterwards.
+	pub fn cancellation(mut self, token: CancellationToken) -> EncoderOptions {
+		self.cancellation = Some(token);
+		self
+	}
+
+	/// Stop with `LzmaError::Cancelled` once `deadline` has passed.  It's checked as often as a
+	/// `CancellationToken` is.
+	pub fn deadline(mut self, deadline: Instant) -> EncoderOptions {
+		self.deadline = Some(deadline);
+		self
+	}
+
+	pub(crate) fn init_stream(&self) -> Result<LzmaStreamWrapper, LzmaError> {
+		let splitting = self.block_size.is_some() || !self.block_list.is_empty();
+
+		// Only the last size in the list may be 0
+		let zero_in_list = self.block_list.iter().rev().skip(1).any(|&size| size == 0);
+
+		if self.block_size == Some(0) || zero_in_list || (splitting && self.format != Format::Xz) {
+			return Err(LzmaError::Options);
+		}
+
+		let mut stream = LzmaStreamWrapper::new();
+		stream.cancel_on(self.cancellation.clone(), self.deadline);
+
+		match self.format {
+			Format::Xz => {
+				capabilities::require_check(self.check)?;
+				capabilities::require_encoder(self.filter_chain()?.filters())?;
+
+				let filters = match self.filters {
+					Some(ref filters) => Some(filters.to_raw()?),
+					None => None,
+				};
+				let threads = self.thread_count()?;
+
+				if threads > 1 {
+					// The multithreaded encoder handles block_size itself
+					stream.stream_encoder_mt(threads, self.block_size.unwrap_or(0), self.preset, filters.as_ref(), self.check.to_raw())?;
+
+					if !self.block_list.is_empty() {
+						stream.split_blocks(None, self.block_list.clone());
+					}
+
+					return Ok(stream);
+				}
+
+				match filters {
+					Some(ref filters) => stream.stream_encoder(filters, self.check.to_raw())?,
+					None => stream.easy_encoder(self.preset, self.check.to_raw())?,
+				}
+
+				if splitting {
+					stream.split_blocks(self.block_size, self.block_list.clone());
+				}
+			},
+			Format::Lzma => {
+				let options = match self.filters {
+					None => LzmaOptions::preset(self.preset)?,
+					Some(ref filters) => match filters.filters() {
+						[Filter::Lzma1(ref options)] => options.clone(),
+						_ => return Err(LzmaError::Options),
+					},
+				};
+
+				capabilities::require_encoder(&[Filter::Lzma1(options.clone())])?;
+				stream.alone_encoder(&options)?;
+			},
+			// liblzma can only decode .lz files
+			Format::Lzip => return Err(LzmaError::Options),
+			Format::Raw => {
+				let filters = self.filter_chain()?;
+
+				capabilities::require_encoder(filters.filters())?;
+				stream.raw_encoder(&filters.to_raw()?)?;
+			},
+		}
+
+		Ok(stream)
+	}
+
+	/// The filters that will be used, which come from the preset unless given explicitly
+	fn filter_chain(&self) -> Result<FilterChain, LzmaError> {
+		match self.filters {
+			Some(ref filters) => Ok(filters.clone()),
+			None => FilterChain::preset(self.preset),
+		}
+	}
+
+	/// The number of threads to use, with 0 meaning one per CPU core
+	fn thread_count(&self) -> Result<u32, LzmaError> {
+		match self.threads {
+			0 => Ok(unsafe { lzma_cputhreads() }.max(1)),
+			threads if threads > LZMA_THREADS_MAX => Err(LzmaError::Options),
+			threads => Ok(threads),
+		}
+	}
+
+	/// The filters, check, thread count and block size for `parallel`, which only supports
+	/// `Format::Xz` with a fixed block size.
+	pub(crate) fn parallel_settings(&self) -> Result<(FilterChain, Check, u32, u64), LzmaError> {
+		if self.format != Format::Xz || !self.block_list.is_empty() || self.block_size == Some(0) {
+			return Err(LzmaError::Options);
+		}
+
+		let filters = self.filter_chain()?;
+		capabilities::require_check(self.check)?;
+		capabilities::require_encoder(filters.filters())?;
+
+		let threads = self.thread_count()?;
+
+		// Like liblzma's multithreaded encoder, default to three times the dictionary size
+		let block_size = self.block_size.unwrap_or_else(|| {
+			let dict_size = filters.filters().iter().filter_map(|filter| match *filter {
+				Filter::Lzma2(ref options) => Some(options.dict_size as u64),
+				_ => None,
+			}).max().unwrap_or(0);
+
+			(3 * dict_size).max(1024 * 1024)
+		});
+
+		Ok((filters, self.check, threads, block_size))
+	}
+}
+
+impl Default for EncoderOptions {
+	fn default() -> EncoderOptions {
+		EncoderOptions::new(6)
+	}
+}
+
+
+/// Options for decompressing data.
+///
+/// # Examples
+///
+/// ```
+/// use lzma::{DecoderOptions, LzmaReader};
+/// use std::io::prelude::*;
+///
+/// let compressed = lzma::compress(b"Time to die", 6).unwrap();
+/// let options = DecoderOptions::new().verify_check(false);
+/// let mut f = LzmaReader::new_decompressor_with_options(&compressed[..], &options).unwrap();
+/// let mut s = String::new();
+///
+/// f.read_to_string(&mut s).unwrap();
+/// assert_eq!(s, "Time to die");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecoderOptions {
+	verify_check: bool,
+	concatenated: bool,
+	memlimit: u64,
+	format: Option<Format>,
+	filters: Option<FilterChain>,
+	max_output: Option<u64>,
+	max_ratio: Option<u64>,
+	trailing_data: TrailingData,
+	cancellation: Option<CancellationToken>,
+	deadline: Option<Instant>,
+}
+
+impl DecoderOptions {
+	/// Options that accept both `.xz` and legacy `.lzma` data, with no memory limit
+	pub fn new() -> DecoderOptions {
+		DecoderOptions {
+			verify_check: true,
+			concatenated: false,
+			memlimit: u64::MAX,
+			format: None,
+			filters: None,
+			max_output: None,
+			max_ratio: None,
+			trailing_data: TrailingData::Ignore,
+			cancellation: None,
+			deadline: None,
+		}
+	}
+
+	/// Whether to verify the integrity check of the data (default true).
+	///
+	/// Skipping verification speeds up decompression when integrity is already guaranteed some
+	/// other way, but corrupt data will go unnoticed.
+	pub fn verify_check(mut self, verify: bool) -> DecoderOptions {
+		self.verify_check = verify;
+		self
+	}
+
+	/// Whether to keep decoding when a stream is followed by another one (default false), like
+	/// the xz tool does.  Files written by `LzmaWriter::append_to` need this.
+	///
+	/// Otherwise decoding stops at the end of the first stream.  Legacy `.lzma` data can't be
+	/// concatenated, so this doesn't affect it.
+	pub fn concatenated(mut self, concatenated: bool) -> DecoderOptions {
+		self.concatenated = concatenated;
+		self
+	}
+
+	/// Fail with `LzmaError::MemLimit` rather than use more than `limit` bytes of memory,
+	/// like xz's `--memlimit`.  Not used by `Format::Raw`.
+	pub fn memlimit(mut self, limit: u64) -> DecoderOptions {
+		self.memlimit = limit;
+		self
+	}
+
+	/// Only accept the given format, rather than detecting it from the data.  `lzma::detect_format`
+	/// can help pick one.
+	///
+	/// By default `.xz` and `.lzma` are accepted, as is `.lz` when liblzma is 5.4 or later.
+	/// `Format::Raw` is never detected, so it has to be asked for explicitly.  `Format::Lzip`
+	/// needs the `lzip` feature, and returns `LzmaError::Unsupported` without it.
+	pub fn format(mut self, format: Format) -> DecoderOptions {
+		self.format = Some(format);
+		self
+	}
+
+	/// The filters `Format::Raw` data was compressed with.  Defaults to xz's default preset,
+	/// which is LZMA2 with an 8 MiB dictionary.
+	pub fn filters(mut self, filters: FilterChain) -> DecoderOptions {
+		self.filters = Some(filters);
+		self
+	}
+
+	/// Fail with `LzmaError::OutputLimit` once decompression produces more than `limit` bytes.
+	///
+	/// Use this to protect against decompression bombs: small inputs that expand to enormous
+	/// amounts of data.
+	pub fn max_output(mut self, limit: u64) -> DecoderOptions {
+		self.max_output = Some(limit);
+		self
+	}
+
+	/// Fail with `LzmaError::OutputLimit` once the decompressed output grows to more than
+	/// `ratio` times the compressed input consumed so far.
+	///
+	/// Typical data expands by less than 10x; highly repetitive data can legitimately go much
+	/// higher, so pick a limit with your data in mind.
+	pub fn max_ratio(mut self, ratio: u64) -> DecoderOptions {
+		self.max_ratio = Some(ratio);
+		self
+	}
+
+	/// What to do with data following the end of the compressed stream (default
+	/// `TrailingData::Ignore`).  See `TrailingData`.
+	pub fn trailing_data(mut self, trailing_data: TrailingData) -> DecoderOptions {
+		self.trailing_data = trailing_data;
+		self
+	}
+
+	/// Stop with `LzmaError::Cancelled` once `token` is cancelled.  See `CancellationToken`.
+	///
+	/// The inner reader or writer can still be recovered with `into_inner` afterwards.
+	pub fn cancellation(mut self, token: CancellationToken) -> DecoderOptions {
+		self.cancellation = Some(token);
+		self
+	}
+
+	/// Stop with `LzmaError::Cancelled` once `deadline` has passed.  It's checked as often as a
+	/// `CancellationToken` is.
+	pub fn deadline(mut self, deadline: Instant) -> DecoderOptions {
+		self.deadline = Some(deadline);
+		self
+	}
+
+	pub(crate) fn init_stream(&self) -> Result<LzmaStreamWrapper, LzmaError> {
+		// LZMA_TELL_ANY_CHECK lets LzmaStreamWrapper find out which check the stream uses
+		let mut flags = LZMA_TELL_ANY_CHECK;
+
+		if !self.verify_check {
+			flags |= LZMA_IGNORE_CHECK;
+		}
+
+		if self.concatenated {
+			flags |= LZMA_CONCATENATED;
+		}
+
+		let mut stream = LzmaStreamWrapper::new();
+		stream.cancel_on(self.cancellation.clone(), self.deadline);
+
+		match self.format {
+			None => stream.auto_decoder(self.memlimit, flags)?,
+			Some(Format::Xz) => stream.stream_decoder(self.memlimit, flags)?,
+			Some(Format::Lzma) => stream.alone_decoder(self.memlimit)?,
+			#[cfg(feature = "lzip")]
+			Some(Format::Lzip) => stream.lzip_decoder(self.memlimit, flags)?,
+			#[cfg(not(feature = "lzip"))]
+			Some(Format::Lzip) => return Err(LzmaError::Unsupported),
+			Some(Format::Raw) => {
+				let filters = match self.filters {
+					Some(ref filters) => filters.clone(),
+					None => FilterChain::preset(6)?,
+				};
+
+				capabilities::require_decoder(filters.filters())?;
+				stream.raw_decoder(&filters.to_raw()?)?;
+			},
+		}
+
+		stream.limit_output(self.max_output, self.max_ratio);
+		stream.trailing_data(self.trailing_data);
+
+		Ok(stream)
+	}
+}
+
+impl Default for DecoderOptions {
+	fn default() -> DecoderOptions {
+		DecoderOptions::new()
+	}
+}
+
+
+/// What decompressors do with data that follows the end of the compressed stream.
+///
+/// Anything after the end can't be part of the compressed data, so it's often a sign of a file
+/// that was truncated and then had something else appended.  `.xz` files may legitimately end
+/// with stream padding: null bytes, in multiples of four.
+///
+/// With `Reject` or `Report`, readers read their input to the end, and writers accept
+/// everything written to them, so that the trailing data can be examined.  With
+/// `DecoderOptions::concatenated`, liblzma itself rejects anything after the last stream that
+/// isn't another stream or stream padding.
+///
+/// # Examples
+///
+/// ```
+/// use lzma::DecoderOptions;
+/// use lzma::options::TrailingData;
+///
+/// let mut compressed = lzma::compress(b"Nexus 6", 6).unwrap();
+/// compressed.extend_from_slice(b"junk");
+///
+/// // Ignored by default
+/// assert_eq!(lzma::decompress(&compressed).unwrap(), b"Nexus 6");
+///
+/// let options = DecoderOptions::new().trailing_data(TrailingData::Reject);
+/// assert!(lzma::decompress_with_options(&compressed, &options).is_err());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TrailingData {
+	/// Stop at the end of the stream, without looking at what follows.  `LzmaWriter` accepts
+	/// anything written after the end and drops it.
+	Ignore,
+	/// Fail with `LzmaError::TrailingData` if anything but stream padding follows the stream
+	Reject,
+	/// Accept anything after the stream, but count it.  See `LzmaReader::trailing_bytes` and
+	/// `LzmaWriter::trailing_bytes`.
+	Report,
+}
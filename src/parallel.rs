@@ -0,0 +1,252 @@
+//! This module implements a parallel compressor that doesn't need liblzma's multithreading.
+//!
+//! The input is split into blocks of a fixed size, which are handed over a bounded queue to a
+//! pool of worker threads that compress them independently of each other.  The blocks are
+//! written out in order as they finish, as a single `.xz` stream followed by an index listing
+//! them.  The result is an ordinary `.xz` file that any xz tool can
+//! decompress, and whose blocks can be decompressed on their own (see `lzma::blocks`).
+//!
+//! liblzma's own multithreaded encoder (`EncoderOptions::threads` with `LzmaWriter`) produces
+//! the same kind of output and should be preferred when available; this is for builds of
+//! liblzma without threading support.
+//!
+//! Up to twice as many blocks as there are threads are held in memory at once, together with
+//! their compressed output, so memory use grows with both the thread count and the block size.
+//! When one block is slow to compress, the others wait for it once that limit is reached.
+//!
+//!
+//! # Examples
+//!
+//! ```
+//! use lzma::EncoderOptions;
+//!
+//! let data = b"More human than human is our motto. ".repeat(10000);
+//! let options = EncoderOptions::new(6).threads(4).block_size(64 * 1024);
+//! let compressed = lzma::parallel::compress(&data, &options).unwrap();
+//!
+//! assert_eq!(lzma::decompress(&compressed).unwrap(), data);
+//! ```
+
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread;
+use lzma_sys::*;
+use error::{LzmaError, LzmaLibResult};
+use block::{Block, BlockEncoder};
+use check::Check;
+use filter::FilterChain;
+use options::EncoderOptions;
+
+
+/// Compresses `buf` into a single `.xz` stream, using `options.threads` threads.  See
+/// `compress_to`.
+pub fn compress(buf: &[u8], options: &EncoderOptions) -> Result<Vec<u8>, LzmaError> {
+	compress_to(buf, Vec::new(), options)
+}
+
+
+/// Compresses everything read from `reader` into a single `.xz` stream written to `writer`,
+/// returning `writer` once done.
+///
+/// The preset or filters, check, thread count and block size are taken from `options`.  The
+/// thread count defaults to 1, so set it with `EncoderOptions::threads`; 0 means one thread per
+/// CPU core.  Without `EncoderOptions::block_size`, blocks are three times the dictionary size,
+/// but at least 1 MiB, like liblzma's multithreaded encoder.  Only `Format::Xz` is supported,
+/// and `EncoderOptions::block_list` isn't; both return `LzmaError::Options`.
+pub fn compress_to<R: Read, W: Write>(mut reader: R, mut writer: W, options: &EncoderOptions) -> Result<W, LzmaError> {
+	let (filters, check, threads, block_size) = options.parallel_settings()?;
+	let mut flags = lzma_stream_flags::new();
+	flags.check = check.to_raw() as u32;
+
+	let mut header = [0u8; LZMA_STREAM_HEADER_SIZE];
+	unsafe {
+		LzmaLibResult::from(lzma_stream_header_encode(&flags, header.as_mut_ptr())).map(|_| ())?;
+	}
+	writer.write_all(&header)?;
+
+	let index = IndexBuilder::new()?;
+	let max_in_flight = threads as usize * 2;
+	// Sends can't block, since no more than `max_in_flight` chunks are ever queued
+	let (job_sender, job_receiver) = mpsc::sync_channel(max_in_flight);
+	let job_receiver = Mutex::new(job_receiver);
+	let (result_sender, result_receiver) = mpsc::channel();
+
+	thread::scope(|scope| {
+		for _ in 0..threads {
+			let job_receiver = &job_receiver;
+			let result_sender = result_sender.clone();
+			let filters = &filters;
+			scope.spawn(move || run_worker(job_receiver, result_sender, filters, check));
+		}
+
+		drop(result_sender);
+
+		// Returning drops the job sender, which stops the workers once they're idle
+		let result = write_blocks(&mut reader, &mut writer, &index, block_size, max_in_flight, job_sender, &result_receiver);
+
+		if result.is_err() {
+			// Don't let the workers compress chunks that won't be written
+			if let Ok(jobs) = job_receiver.lock() {
+				while jobs.try_recv().is_ok() {}
+			}
+		}
+
+		result
+	})?;
+
+	let index = index.encode()?;
+	writer.write_all(&index)?;
+
+	let mut footer = [0u8; LZMA_STREAM_HEADER_SIZE];
+	flags.backward_size = index.len() as u64;
+	unsafe {
+		LzmaLibResult::from(lzma_stream_footer_encode(&flags, footer.as_mut_ptr())).map(|_| ())?;
+	}
+	writer.write_all(&footer)?;
+
+	Ok(writer)
+}
+
+
+/// A chunk of input, numbered in the order it was read
+type Job = (u64, Vec<u8>);
+
+/// The number of a chunk, its size, and its compressed block with the unpadded size
+type JobResult = (u64, u64, Result<(Vec<u8>, u64), LzmaError>);
+
+
+/// Hands chunks of `reader` to the workers through `jobs`, and writes the finished blocks to
+/// `writer` in order, keeping at most `max_in_flight` chunks that are read but not yet written
+fn write_blocks<R: Read, W: Write>(reader: &mut R, writer: &mut W, index: &IndexBuilder, block_size: u64, max_in_flight: usize, jobs: SyncSender<Job>, results: &Receiver<JobResult>) -> Result<(), LzmaError> {
+	// Blocks that finished before the ones ahead of them
+	let mut finished = BTreeMap::new();
+	let mut read = 0;
+	let mut written = 0;
+	let mut eof = false;
+
+	loop {
+		while !eof && read - written < max_in_flight as u64 {
+			let chunk = read_chunk(reader, block_size)?;
+
+			if chunk.is_empty() {
+				eof = true;
+			} else {
+				jobs.send((read, chunk)).map_err(|_| LzmaError::Other)?;
+				read += 1;
+			}
+		}
+
+		if written == read {
+			return Ok(());
+		}
+
+		// The workers only all stop if they panicked
+		let (seq, size, block) = results.recv().map_err(|_| LzmaError::Other)?;
+		finished.insert(seq, (size, block));
+
+		while let Some((size, block)) = finished.remove(&written) {
+			let (data, unpadded_size) = block?;
+			writer.write_all(&data)?;
+			index.append(unpadded_size, size)?;
+			written += 1;
+		}
+	}
+}
+
+
+/// Compresses chunks from `jobs` until there are no more
+fn run_worker(jobs: &Mutex<Receiver<Job>>, results: Sender<JobResult>, filters: &FilterChain, check: Check) {
+	loop {
+		// The lock is only held while waiting for a chunk, not while compressing it
+		let job = match jobs.lock() {
+			Ok(jobs) => jobs.recv(),
+			Err(_) => return,
+		};
+
+		let (seq, chunk) = match job {
+			Ok(job) => job,
+			Err(_) => return,
+		};
+
+		let block = panic::catch_unwind(AssertUnwindSafe(|| compress_block(&chunk, filters, check))).unwrap_or(Err(LzmaError::Other));
+
+		if results.send((seq, chunk.len() as u64, block)).is_err() {
+			return;
+		}
+	}
+}
+
+
+/// Reads up to `size` bytes, stopping early only at the end of the input
+fn read_chunk<R: Read>(reader: &mut R, size: u64) -> Result<Vec<u8>, LzmaError> {
+	let mut chunk = Vec::new();
+
+	loop {
+		match reader.by_ref().take(size - chunk.len() as u64).read_to_end(&mut chunk) {
+			Ok(_) => return Ok(chunk),
+			Err(ref err) if err.kind() == ErrorKind::Interrupted => (),
+			Err(err) => return Err(err.into()),
+		}
+	}
+}
+
+
+/// Compresses `chunk` into a complete block, header included.  Returns the block and its
+/// unpadded size.
+fn compress_block(chunk: &[u8], filters: &FilterChain, check: Check) -> Result<(Vec<u8>, u64), LzmaError> {
+	let block = Block::new(filters.clone(), check);
+	let mut encoder = BlockEncoder::new(Vec::new(), &block)?;
+	encoder.write_all(chunk)?;
+	let (data, block) = encoder.finish()?;
+
+	// The sizes are known now, so they go in the header, like xz's multithreaded encoder does
+	let mut output = block.encode_header()?;
+	output.extend_from_slice(&data);
+
+	Ok((output, block.unpadded_size().ok_or(LzmaError::Other)?))
+}
+
+
+/// Owns an `lzma_index` that the index of the stream is built up in
+struct IndexBuilder(*mut lzma_index);
+
+impl IndexBuilder {
+	fn new() -> Result<IndexBuilder, LzmaError> {
+		let index = unsafe { lzma_index_init(ptr::null()) };
+
+		if index.is_null() {
+			return Err(LzmaError::Mem);
+		}
+
+		Ok(IndexBuilder(index))
+	}
+
+	fn append(&self, unpadded_size: u64, uncompressed_size: u64) -> Result<(), LzmaError> {
+		unsafe {
+			LzmaLibResult::from(lzma_index_append(self.0, ptr::null(), unpadded_size, uncompressed_size)).map(|_| ())
+		}
+	}
+
+	fn encode(&self) -> Result<Vec<u8>, LzmaError> {
+		let mut output = vec![0u8; unsafe { lzma_index_size(self.0) } as usize];
+		let mut out_pos = 0;
+
+		unsafe {
+			LzmaLibResult::from(lzma_index_buffer_encode(self.0, output.as_mut_ptr(), &mut out_pos, output.len())).map(|_| ())?;
+		}
+
+		Ok(output)
+	}
+}
+
+impl Drop for IndexBuilder {
+	fn drop(&mut self) {
+		unsafe {
+			lzma_index_end(self.0, ptr::null());
+		}
+	}
+}
@@ -19,12 +19,32 @@
 //! f.read_to_string(&mut s).unwrap();
 //! println!("{}", s);
 //! ```
+//!
+//! Errors from the wrapped object are passed on without losing any data, so reading can carry
+//! on after `WouldBlock` once more input is available.  `Interrupted` is retried automatically.
+//!
+//! `LzmaReader` also implements `BufRead`, so there's no need to wrap it in a `BufReader` to read
+//! lines:
+//!
+//! ```no_run
+//! use lzma::LzmaReader;
+//! use std::io::prelude::*;
+//! use std::fs::File;
+//!
+//! let f = LzmaReader::new_decompressor(File::open("access.log.xz").unwrap()).unwrap();
+//!
+//! for line in f.lines() {
+//!     println!("{}", line.unwrap());
+//! }
+//! ```
 
-use std::io::{self, Read};
+use std::io::{self, BufRead, ErrorKind, IoSliceMut, Read};
+use std::mem;
 use lzma_sys::*;
-use std;
 use error::LzmaError;
 use ::Direction;
+use options::{EncoderOptions, DecoderOptions};
+use check::Check;
 use lzma_stream_wrapper::LzmaStreamWrapper;
 
 
@@ -37,7 +57,12 @@ pub struct LzmaReader<T> {
 	buffer: Vec<u8>,
 	buffer_offset: usize,
 	buffer_len: usize,
-	direction: Direction,
+	// Output for BufRead, only allocated once fill_buf is used
+	output: Vec<u8>,
+	output_offset: usize,
+	output_len: usize,
+	// An error read_vectored hit after reading some data, for the next call to return
+	error: Option<io::Error>,
 }
 
 
@@ -50,48 +75,86 @@ impl<T: Read> LzmaReader<T> {
 		LzmaReader::with_capacity(DEFAULT_BUF_SIZE, inner, Direction::Decompress, 0)
 	}
 
+	/// Creates a compressor using the given options.  See `EncoderOptions`.
+	pub fn new_compressor_with_options(inner: T, options: &EncoderOptions) -> Result<LzmaReader<T>, LzmaError> {
+		Ok(LzmaReader::from_stream(DEFAULT_BUF_SIZE, inner, options.init_stream()?))
+	}
+
+	/// Creates a decompressor using the given options.  See `DecoderOptions`.
+	pub fn new_decompressor_with_options(inner: T, options: &DecoderOptions) -> Result<LzmaReader<T>, LzmaError> {
+		Ok(LzmaReader::from_stream(DEFAULT_BUF_SIZE, inner, options.init_stream()?))
+	}
+
 	pub fn with_capacity(capacity: usize, inner: T, direction: Direction, preset: u32) -> Result<LzmaReader<T>, LzmaError> {
-		let mut reader = LzmaReader {
+		let stream = match direction {
+			Direction::Compress => EncoderOptions::new(preset).init_stream()?,
+			Direction::Decompress => DecoderOptions::new().init_stream()?,
+		};
+
+		Ok(LzmaReader::from_stream(capacity, inner, stream))
+	}
+
+	/// Wraps an already initialized stream
+	pub(crate) fn from_stream(capacity: usize, inner: T, stream: LzmaStreamWrapper) -> LzmaReader<T> {
+		LzmaReader {
 			inner,
-			stream: LzmaStreamWrapper::new(),
+			stream,
 			buffer: vec![0; capacity],
 			buffer_offset: 0,
 			buffer_len: 0,
-			direction,
-		};
-
-		match reader.direction {
-			Direction::Compress => {
-				reader.stream.easy_encoder(preset, lzma_check::LzmaCheckCrc64)?
-			},
-			Direction::Decompress => {
-				reader.stream.stream_decoder(std::u64::MAX, 0)?
-			},
+			output: Vec::new(),
+			output_offset: 0,
+			output_len: 0,
+			error: None,
 		}
+	}
 
-		Ok(reader)
+	pub(crate) fn stream(&self) -> &LzmaStreamWrapper {
+		&self.stream
 	}
 
-	pub fn into_inner(self) -> T { self.inner }
-}
+	/// The integrity check used by the stream being decompressed.
+	///
+	/// This is None until the stream header has been read, and always None for compressors.
+	/// Legacy `.lzma` files have no check, so they report `Check::None`.
+	pub fn check_type(&self) -> Option<Check> {
+		self.stream.check()
+	}
 
+	/// Number of bytes that followed the end of the compressed stream, not counting stream
+	/// padding.  Only counted with `TrailingData::Report`, and only complete once the reader has
+	/// returned EOF.
+	pub fn trailing_bytes(&self) -> u64 {
+		self.stream.trailing_bytes()
+	}
+
+	/// Output buffered by `fill_buf` but not yet consumed is lost.
+	pub fn into_inner(self) -> T { self.inner }
 
-impl<R: Read> Read for LzmaReader<R> {
 	/// Reads data from the wrapped object, applies compression/decompression, and puts the results
-	/// into buf.
-	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+	/// into buf, bypassing the output buffer.
+	fn read_unbuffered(&mut self, buf: &mut [u8]) -> io::Result<usize> {
 		// Our code doesn't handle buf.len() being 0, so exit early
 		if buf.is_empty() {
 			return Ok(0);
 		}
 
+		if let Some(err) = self.error.take() {
+			return Err(err);
+		}
+
 		loop {
 			let mut action = lzma_action::LzmaRun;
 
 			// If our internal read buffer is empty, re-fill it by calling read on the inner Read object.
 			if self.buffer_len == 0 {
 				self.buffer_offset = 0;
-				self.buffer_len = self.inner.read(&mut self.buffer)?;
+				self.buffer_len = match self.inner.read(&mut self.buffer) {
+					Ok(len) => len,
+					Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+					// Nothing has been lost, so reading can carry on after WouldBlock
+					Err(err) => return Err(err),
+				};
 
 				if self.buffer_len == 0 {
 					action = lzma_action::LzmaFinish;
@@ -106,7 +169,7 @@ impl<R: Read> Read for LzmaReader<R> {
 			let stream_end = match result.ret {
 				Ok(lzma_ret::LzmaStreamEnd) => true,
 				Ok(_) => false,
-				Err(err) => return Err(io::Error::new(io::ErrorKind::Other, err)),
+				Err(err) => return Err(io::Error::other(err)),
 			};
 
 			// We have to loop until we get at least 1 byte or EOF, because most users of
@@ -117,3 +180,81 @@ impl<R: Read> Read for LzmaReader<R> {
 		}
 	}
 }
+
+
+impl<R: Read> Read for LzmaReader<R> {
+	/// Reads data from the wrapped object, applies compression/decompression, and puts the results
+	/// into buf.
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		// Anything left over from fill_buf comes first
+		if self.output_offset < self.output_len {
+			let available = &self.output[self.output_offset..self.output_len];
+			let len = available.len().min(buf.len());
+			buf[..len].copy_from_slice(&available[..len]);
+			self.consume(len);
+			return Ok(len);
+		}
+
+		self.read_unbuffered(buf)
+	}
+
+	/// Fills the slices in order.  Once some data has been read, the next slice is only started
+	/// if there's input left over from the wrapped object.
+	///
+	/// If an error comes up after some data has been read, that data is returned and the error
+	/// is returned by the next call.
+	fn read_vectored(&mut self, bufs: &mut [IoSliceMut]) -> io::Result<usize> {
+		let mut total = 0;
+
+		for buf in bufs.iter_mut().filter(|buf| !buf.is_empty()) {
+			if total > 0 && self.buffer_len == 0 && self.output_offset >= self.output_len {
+				break;
+			}
+
+			let len = match self.read(buf) {
+				Ok(len) => len,
+				// Nothing is lost by WouldBlock, so it can simply be tried again later
+				Err(ref err) if total > 0 && err.kind() == ErrorKind::WouldBlock => break,
+				// What was read into the earlier slices mustn't be lost, and neither must the error
+				Err(err) if total > 0 => {
+					self.error = Some(err);
+					break;
+				},
+				Err(err) => return Err(err),
+			};
+			total += len;
+
+			if len < buf.len() {
+				break;
+			}
+		}
+
+		Ok(total)
+	}
+}
+
+
+impl<R: Read> BufRead for LzmaReader<R> {
+	fn fill_buf(&mut self) -> io::Result<&[u8]> {
+		if self.output_offset >= self.output_len {
+			if self.output.is_empty() {
+				self.output = vec![0; self.buffer.len().max(DEFAULT_BUF_SIZE)];
+			}
+
+			let mut output = mem::take(&mut self.output);
+			let result = self.read_unbuffered(&mut output);
+			self.output = output;
+
+			// On error the old output stays consumed, rather than being handed out again
+			let len = result?;
+			self.output_offset = 0;
+			self.output_len = len;
+		}
+
+		Ok(&self.output[self.output_offset..self.output_len])
+	}
+
+	fn consume(&mut self, amt: usize) {
+		self.output_offset = (self.output_offset + amt).min(self.output_len);
+	}
+}
@@ -0,0 +1,485 @@
+//! This module implements `salvage`, which recovers what it can from damaged `.xz` data.
+//!
+//! Each block of an `.xz` file can be decompressed on its own, so damage to one block doesn't
+//! have to take the rest of the file with it.  Blocks are found through the indexes when those
+//! are intact, and otherwise by scanning the file for block headers, which are protected by
+//! their own CRC32.  Blocks that fail to decompress are left out of the output and reported as
+//! lost.
+//!
+//! xz's single-threaded encoder puts everything into one block, leaving nothing to salvage.
+//! Files made with `xz -T`, `EncoderOptions::threads` or `EncoderOptions::block_size` have many
+//! blocks, and can be salvaged block by block.
+
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Take, Write};
+use error::LzmaError;
+use block::{Block, BlockDecoder};
+use check::Check;
+use xz::{BlockHeader, FileInfo, IndexRecord, StreamFooter, StreamHeader, STREAM_HEADER_SIZE};
+
+
+/// Block headers are at most 1024 bytes long
+const BLOCK_HEADER_SIZE_MAX: usize = 1024;
+
+const WINDOW_SIZE: usize = 64 * 1024;
+
+/// Decompressed blocks up to this size are held in memory until they're verified
+const BLOCK_BUFFER_MAX: usize = 64 * 1024 * 1024;
+
+
+/// A part of the data that `salvage` couldn't recover
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LostRange {
+	/// Where the damaged data starts in the file
+	pub compressed_offset: u64,
+	/// Size of the damaged data in the file
+	pub compressed_size: u64,
+	/// Where the lost data would have been in the decompressed output.  When the index is
+	/// damaged, this only counts lost data whose size is known.
+	pub uncompressed_offset: u64,
+	/// Size of the lost data, if the index or the block header records it
+	pub uncompressed_size: Option<u64>,
+}
+
+
+/// What `salvage` recovered
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SalvageReport {
+	recovered_blocks: u64,
+	recovered_size: u64,
+	lost: Vec<LostRange>,
+	used_index: bool,
+}
+
+impl SalvageReport {
+	/// Number of blocks that were decompressed successfully
+	pub fn recovered_blocks(&self) -> u64 {
+		self.recovered_blocks
+	}
+
+	/// Size of the recovered data, which is what was written to the output
+	pub fn recovered_size(&self) -> u64 {
+		self.recovered_size
+	}
+
+	/// The damaged parts of the file, in order
+	pub fn lost(&self) -> &[LostRange] {
+		&self.lost
+	}
+
+	/// Whether blocks were found through the indexes, rather than by scanning the file
+	pub fn used_index(&self) -> bool {
+		self.used_index
+	}
+
+	/// Whether nothing was lost
+	pub fn is_complete(&self) -> bool {
+		self.lost.is_empty()
+	}
+}
+
+
+/// Decompresses every intact block of the `.xz` data in `reader` to `output`, skipping damaged
+/// ones, and reports which parts of the data were lost.
+///
+/// Damaged data is never written to `output`; a block is only written once its integrity
+/// check has been verified.  Blocks of up to 64 MiB are held in memory until then.  Bigger
+/// ones are decompressed twice instead, once to verify them and again to write them out.
+///
+/// Returns `LzmaError::Format` if no `.xz` stream is found at all.  Errors from `reader` and
+/// `output` are returned as `LzmaError::Io`.
+///
+/// # Examples
+///
+/// ```
+/// use lzma::EncoderOptions;
+/// use std::io::{Cursor, Write};
+///
+/// let data = b"I've seen things you people wouldn't believe. ".repeat(1000);
+/// let options = EncoderOptions::new(6).block_size(16 * 1024);
+/// let mut writer = lzma::LzmaWriter::new_compressor_with_options(Vec::new(), &options).unwrap();
+/// writer.write_all(&data).unwrap();
+/// let mut compressed = writer.finish().unwrap();
+///
+/// // Damage the first block
+/// compressed[100] ^= 0xff;
+///
+/// let mut recovered = Vec::new();
+/// let report = lzma::salvage(Cursor::new(&compressed), &mut recovered).unwrap();
+///
+/// assert_eq!(report.lost()[0].uncompressed_offset, 0);
+/// assert_eq!(report.lost()[0].uncompressed_size, Some(16 * 1024));
+/// assert_eq!(recovered, &data[16 * 1024..]);
+/// ```
+pub fn salvage<R: Read + Seek, W: Write>(mut reader: R, mut output: W) -> Result<SalvageReport, LzmaError> {
+	let mut report = SalvageReport {
+		recovered_blocks: 0,
+		recovered_size: 0,
+		lost: Vec::new(),
+		used_index: false,
+	};
+
+	match FileInfo::from_reader(&mut reader) {
+		Ok(info) => {
+			report.used_index = true;
+			salvage_indexed(&mut reader, &mut output, &info, &mut report)?;
+		},
+		Err(LzmaError::Io(err)) => return Err(LzmaError::Io(err)),
+		Err(_) => Scanner::new(&mut reader, &mut output, &mut report).run()?,
+	}
+
+	Ok(report)
+}
+
+
+/// Salvages a file whose structure is intact, so only the contents of blocks can be damaged
+fn salvage_indexed<R: Read + Seek, W: Write>(reader: &mut R, output: &mut W, info: &FileInfo, report: &mut SalvageReport) -> Result<(), LzmaError> {
+	let mut uncompressed_offset = 0;
+
+	for stream in info.streams() {
+		for (record, offset) in stream.index.records.iter().zip(stream.block_offsets()) {
+			let decoded = match stream.header.check() {
+				Some(check) => decode_block(reader, output, offset, check, Some(record))?,
+				None => None,
+			};
+
+			match decoded {
+				Some((size, _)) => {
+					report.recovered_blocks += 1;
+					report.recovered_size += size;
+				},
+				None => report.lost.push(LostRange {
+					compressed_offset: offset,
+					compressed_size: record.total_size(),
+					uncompressed_offset,
+					uncompressed_size: Some(record.uncompressed_size),
+				}),
+			}
+
+			uncompressed_offset += record.uncompressed_size;
+		}
+	}
+
+	Ok(())
+}
+
+
+/// Decodes the block starting at `offset`, including its header, to `output`.  `record` is
+/// the block's index entry, if known, which the block's sizes are checked against.  Returns
+/// the decompressed size and the block.
+///
+/// Returns None, without writing anything, if the block is damaged or uses filters this build
+/// of liblzma can't decode.
+fn decode_block<R: Read + Seek, W: Write>(reader: &mut R, output: &mut W, offset: u64, check: Check, record: Option<&IndexRecord>) -> Result<Option<(u64, Block)>, LzmaError> {
+	// Verify the whole block before writing any of it
+	let mut decoder = match open_block(reader, offset, check, record)? {
+		Some(decoder) => decoder,
+		None => return Ok(None),
+	};
+	let mut data = Vec::new();
+
+	match decoder.by_ref().take(BLOCK_BUFFER_MAX as u64 + 1).read_to_end(&mut data) {
+		Ok(_) => (),
+		Err(ref err) if is_damage(err) => return Ok(None),
+		Err(err) => return Err(err.into()),
+	}
+
+	// The decoder reached the end, so the check has been verified
+	if data.len() <= BLOCK_BUFFER_MAX {
+		output.write_all(&data)?;
+		return Ok(Some((data.len() as u64, decoder.block())));
+	}
+
+	// Too big to hold on to, so verify the rest and then decompress it all again
+	drop(data);
+
+	match io::copy(&mut decoder, &mut io::sink()) {
+		Ok(_) => (),
+		Err(ref err) if is_damage(err) => return Ok(None),
+		Err(err) => return Err(err.into()),
+	}
+
+	let block = decoder.block();
+	let mut decoder = open_block(reader, offset, check, record)?.ok_or(LzmaError::Other)?;
+	let size = io::copy(&mut decoder, output)?;
+
+	Ok(Some((size, block)))
+}
+
+
+/// Sets up a decoder for the block starting at `offset`, or returns None if its header is
+/// damaged or unsupported
+fn open_block<'r, R: Read + Seek>(reader: &'r mut R, offset: u64, check: Check, record: Option<&IndexRecord>) -> Result<Option<BlockDecoder<Take<&'r mut R>>>, LzmaError> {
+	reader.seek(SeekFrom::Start(offset))?;
+
+	let mut header = [0u8; BLOCK_HEADER_SIZE_MAX];
+	let header = match read_block_header(reader, &mut header)? {
+		Some(header) => header,
+		None => return Ok(None),
+	};
+
+	let mut block = match Block::decode_header(header, check) {
+		Ok(block) => block,
+		Err(_) => return Ok(None),
+	};
+
+	if let Some(record) = record {
+		if block.set_unpadded_size(record.unpadded_size).is_err() {
+			return Ok(None);
+		}
+
+		block.set_uncompressed_size(Some(record.uncompressed_size));
+	}
+
+	// Don't read past the end of the block if its size is known
+	let limit = block.total_size().map_or(u64::MAX, |size| size - header.len() as u64);
+
+	match BlockDecoder::new(reader.take(limit), &block) {
+		Ok(decoder) => Ok(Some(decoder)),
+		Err(_) => Ok(None),
+	}
+}
+
+
+/// Reads a block header into `buf`, returning None if it's cut short by the end of the file
+/// or is actually an Index Indicator
+fn read_block_header<'a, R: Read>(reader: &mut R, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, LzmaError> {
+	match reader.read_exact(&mut buf[..1]) {
+		Ok(()) => (),
+		Err(ref err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+		Err(err) => return Err(err.into()),
+	}
+
+	if buf[0] == 0 {
+		return Ok(None);
+	}
+
+	let header_size = Block::header_size_from_first_byte(buf[0]);
+
+	match reader.read_exact(&mut buf[1..header_size]) {
+		Ok(()) => Ok(Some(&buf[..header_size])),
+		Err(ref err) if err.kind() == ErrorKind::UnexpectedEof => Ok(None),
+		Err(err) => Err(err.into()),
+	}
+}
+
+
+/// Whether `buf` passes the cheap checks on a block header: a non-zero header size, and no
+/// reserved flag bits set
+fn could_be_block_header(buf: &[u8]) -> bool {
+	buf.len() >= 2 && buf[0] != 0 && buf[1] & 0x3c == 0
+}
+
+
+/// Whether an error from a decoder was caused by the data, rather than by the reader
+fn is_damage(err: &io::Error) -> bool {
+	err.get_ref().is_some_and(|err| err.is::<LzmaError>())
+}
+
+
+/// What the scanner expects to find next
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Expect {
+	/// A stream header, since we're at the start of the file or after a stream footer
+	StreamHeader,
+	/// A block, or the index
+	Block,
+}
+
+/// What an unrecognised stretch of the file seems to be
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Gap {
+	/// The index, which is fine as long as it's followed by a stream footer
+	Index,
+	/// Stream padding, which is fine as long as it's all zeros
+	Padding,
+	/// Damaged data
+	Lost,
+}
+
+/// Searches a file with a damaged structure for anything that looks like a stream header, block
+/// or stream footer, 4 bytes at a time.  Whatever lies between them is either an index, stream
+/// padding, or lost.
+struct Scanner<'a, R: 'a, W: 'a> {
+	reader: &'a mut R,
+	output: &'a mut W,
+	report: &'a mut SalvageReport,
+	window: Window,
+	pos: u64,
+	expect: Expect,
+	/// The check of the last stream header found
+	check: Option<Check>,
+	found_stream: bool,
+	/// Start, kind and uncompressed size of the unrecognised data before `pos`
+	gap: Option<(u64, Gap, Option<u64>)>,
+	uncompressed_offset: u64,
+}
+
+impl<'a, R: Read + Seek, W: Write> Scanner<'a, R, W> {
+	fn new(reader: &'a mut R, output: &'a mut W, report: &'a mut SalvageReport) -> Scanner<'a, R, W> {
+		Scanner {
+			reader,
+			output,
+			report,
+			window: Window::new(),
+			pos: 0,
+			expect: Expect::StreamHeader,
+			check: None,
+			found_stream: false,
+			gap: None,
+			uncompressed_offset: 0,
+		}
+	}
+
+	fn run(mut self) -> Result<(), LzmaError> {
+		let file_size = self.reader.seek(SeekFrom::End(0))?;
+
+		while self.pos < file_size {
+			let (header, footer, block_header, word) = {
+				let buf = self.window.get(self.reader, self.pos, BLOCK_HEADER_SIZE_MAX)?;
+				let mut word = [0u8; 4];
+				let len = buf.len().min(4);
+				word[..len].copy_from_slice(&buf[..len]);
+
+				// Checking the header CRC at every position would be slow, so rule out most
+				// positions by the header size and reserved flag bits first
+				let block_header = if could_be_block_header(buf) { BlockHeader::parse(buf).ok() } else { None };
+
+				(StreamHeader::parse(buf).ok(), StreamFooter::parse(buf).ok(), block_header, word)
+			};
+
+			if let Some(header) = header {
+				self.end_gap(false);
+				self.expect = Expect::Block;
+				self.check = header.check();
+				self.found_stream = true;
+				self.pos += STREAM_HEADER_SIZE as u64;
+				continue;
+			}
+
+			if footer.is_some() {
+				self.end_gap(true);
+				self.expect = Expect::StreamHeader;
+				self.pos += STREAM_HEADER_SIZE as u64;
+				continue;
+			}
+
+			if let (Some(block_header), Some(check)) = (block_header, self.check) {
+				match decode_block(self.reader, self.output, self.pos, check, None)? {
+					Some((size, block)) => {
+						self.end_gap(false);
+						self.report.recovered_blocks += 1;
+						self.report.recovered_size += size;
+						self.uncompressed_offset += size;
+						self.expect = Expect::Block;
+						self.pos += block.total_size().ok_or(LzmaError::Other)?;
+					},
+					None => {
+						// A damaged block.  Whatever follows it up to the next thing found is lost.
+						self.end_gap(false);
+						self.gap = Some((self.pos, Gap::Lost, block_header.uncompressed_size));
+						self.pos += 4;
+					},
+				}
+
+				continue;
+			}
+
+			self.gap = match self.gap {
+				None => {
+					let kind = match self.expect {
+						Expect::Block if word[0] == 0 => Gap::Index,
+						Expect::StreamHeader if word == [0; 4] => Gap::Padding,
+						_ => Gap::Lost,
+					};
+
+					Some((self.pos, kind, None))
+				},
+				Some((start, Gap::Padding, size)) if word != [0; 4] => Some((start, Gap::Lost, size)),
+				gap => gap,
+			};
+			self.pos += 4;
+		}
+
+		self.end_gap(false);
+
+		if !self.found_stream {
+			return Err(LzmaError::Format);
+		}
+
+		Ok(())
+	}
+
+	/// Ends the unrecognised data at `pos`, recording it as lost unless it turned out to be an
+	/// index or padding.  `at_footer` is whether a stream footer follows it.
+	fn end_gap(&mut self, at_footer: bool) {
+		let (start, kind, uncompressed_size) = match self.gap.take() {
+			Some(gap) => gap,
+			None => return,
+		};
+
+		match kind {
+			Gap::Index if at_footer => return,
+			Gap::Padding if !at_footer => return,
+			_ => (),
+		}
+
+		self.report.lost.push(LostRange {
+			compressed_offset: start,
+			compressed_size: self.pos - start,
+			uncompressed_offset: self.uncompressed_offset,
+			uncompressed_size,
+		});
+		self.uncompressed_offset += uncompressed_size.unwrap_or(0);
+	}
+}
+
+
+/// Buffers part of a file, so that scanning doesn't need a seek and read for every position
+struct Window {
+	start: u64,
+	data: Vec<u8>,
+	/// Whether `data` reaches the end of the file
+	eof: bool,
+}
+
+impl Window {
+	fn new() -> Window {
+		Window {
+			start: 0,
+			data: Vec::new(),
+			eof: false,
+		}
+	}
+
+	/// Up to `len` bytes of the file starting at `pos`.  Fewer are returned near the end of
+	/// the file.
+	fn get<R: Read + Seek>(&mut self, reader: &mut R, pos: u64, len: usize) -> Result<&[u8], LzmaError> {
+		let end = self.start + self.data.len() as u64;
+
+		if pos < self.start || (pos + len as u64 > end && !self.eof) {
+			self.start = pos;
+			self.data.resize(WINDOW_SIZE.max(len), 0);
+			self.eof = false;
+			reader.seek(SeekFrom::Start(pos))?;
+
+			let mut filled = 0;
+
+			while filled < self.data.len() {
+				match reader.read(&mut self.data[filled..]) {
+					Ok(0) => {
+						self.eof = true;
+						break;
+					},
+					Ok(n) => filled += n,
+					Err(ref err) if err.kind() == ErrorKind::Interrupted => (),
+					Err(err) => return Err(err.into()),
+				}
+			}
+
+			self.data.truncate(filled);
+		}
+
+		let from = ((pos - self.start) as usize).min(self.data.len());
+		Ok(&self.data[from..self.data.len().min(from + len)])
+	}
+}
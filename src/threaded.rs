@@ -0,0 +1,217 @@
+//! This module implements `ThreadedLzmaWriter`.
+//!
+//! `ThreadedLzmaWriter` compresses on a background thread, so that writing to it only costs a
+//! copy.  Written data is collected into chunks, which are handed over a bounded channel to a
+//! worker thread that owns an ordinary `LzmaWriter`.  When the worker falls behind, writes
+//! block until it catches up, so memory use stays bounded.
+//!
+//! Compression still happens on a single thread.  For compressing on several threads, see
+//! `EncoderOptions::threads`, which can be combined with this.
+//!
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use lzma::ThreadedLzmaWriter;
+//! use std::io::prelude::*;
+//! use std::fs::File;
+//!
+//! let f = File::create("foo.xz").unwrap();
+//! let mut f = ThreadedLzmaWriter::new(f, 6).unwrap();
+//!
+//! write!(f, "It's a small world!").unwrap();
+//! f.finish().unwrap();
+//! ```
+
+use std::io::{self, ErrorKind, Write};
+use std::mem;
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
+use error::LzmaError;
+use options::EncoderOptions;
+use writer::LzmaWriter;
+
+
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+const DEFAULT_QUEUE_LEN: usize = 4;
+
+
+enum Message {
+	Data(Vec<u8>),
+	Finish,
+}
+
+
+pub struct ThreadedLzmaWriter<W: Write + Send + 'static> {
+	// Both are None once the worker has been joined
+	sender: Option<SyncSender<Message>>,
+	worker: Option<JoinHandle<Result<W, LzmaError>>>,
+	// Data written but not yet handed to the worker
+	chunk: Vec<u8>,
+	chunk_size: usize,
+	failed: bool,
+}
+
+
+impl<W: Write + Send + 'static> ThreadedLzmaWriter<W> {
+	pub fn new(inner: W, preset: u32) -> Result<ThreadedLzmaWriter<W>, LzmaError> {
+		ThreadedLzmaWriter::new_with_options(inner, &EncoderOptions::new(preset))
+	}
+
+	/// Creates a compressor using the given options.  See `EncoderOptions`.
+	pub fn new_with_options(inner: W, options: &EncoderOptions) -> Result<ThreadedLzmaWriter<W>, LzmaError> {
+		ThreadedLzmaWriter::with_capacity(DEFAULT_CHUNK_SIZE, DEFAULT_QUEUE_LEN, inner, options)
+	}
+
+	/// Hands data to the worker in chunks of `chunk_size` bytes, and lets up to `queue_len`
+	/// chunks wait for the worker before writes block.
+	pub fn with_capacity(chunk_size: usize, queue_len: usize, inner: W, options: &EncoderOptions) -> Result<ThreadedLzmaWriter<W>, LzmaError> {
+		// Set up the encoder here, so that bad options are reported right away
+		let writer = LzmaWriter::new_compressor_with_options(inner, options)?;
+		let (sender, receiver) = mpsc::sync_channel(queue_len);
+
+		let worker = thread::spawn(move || {
+			let mut writer = writer;
+
+			for message in receiver {
+				match message {
+					Message::Data(chunk) => if let Err(err) = writer.write_all(&chunk) {
+						writer.into_inner();
+						return Err(err.into());
+					},
+					Message::Finish => return writer.finish(),
+				}
+			}
+
+			// Dropped without finishing; leave the output unfinished, like LzmaWriter does
+			Ok(writer.into_inner())
+		});
+
+		Ok(ThreadedLzmaWriter {
+			sender: Some(sender),
+			worker: Some(worker),
+			chunk: Vec::with_capacity(chunk_size),
+			chunk_size: chunk_size.max(1),
+			failed: false,
+		})
+	}
+
+	/// Waits for all written data to be compressed, finishes the stream, and returns the inner
+	/// writer.
+	///
+	/// If the worker failed, its error is returned.
+	pub fn finish(mut self) -> Result<W, LzmaError> {
+		if self.worker.is_none() {
+			return Err(LzmaError::Io(stopped()));
+		}
+
+		let chunk = mem::take(&mut self.chunk);
+
+		if !chunk.is_empty() {
+			// If the worker has stopped, joining it below gives the reason
+			let _ = self.send(Message::Data(chunk));
+		}
+
+		let _ = self.send(Message::Finish);
+		self.join()
+	}
+
+	fn send(&mut self, message: Message) -> Result<(), LzmaError> {
+		match self.sender.as_ref().map(|sender| sender.send(message)) {
+			Some(Ok(())) => Ok(()),
+			_ => Err(LzmaError::Io(stopped())),
+		}
+	}
+
+	/// Sends the data collected so far to the worker, blocking if it's behind
+	fn send_chunk(&mut self) -> io::Result<()> {
+		let chunk = mem::replace(&mut self.chunk, Vec::with_capacity(self.chunk_size));
+
+		if self.send(Message::Data(chunk)).is_err() {
+			// The worker has stopped, which only happens when it fails
+			return Err(self.worker_error());
+		}
+
+		Ok(())
+	}
+
+	/// Joins a worker that stopped early, returning its error
+	fn worker_error(&mut self) -> io::Error {
+		self.failed = true;
+
+		match self.join() {
+			Err(LzmaError::Io(err)) => err,
+			Err(err) => io::Error::other(err),
+			Ok(_) => stopped(),
+		}
+	}
+
+	fn join(&mut self) -> Result<W, LzmaError> {
+		self.sender = None;
+
+		match self.worker.take().map(JoinHandle::join) {
+			Some(Ok(result)) => result,
+			// The worker panicked
+			Some(Err(_)) => Err(LzmaError::Other),
+			None => Err(LzmaError::Io(stopped())),
+		}
+	}
+}
+
+
+fn stopped() -> io::Error {
+	io::Error::new(ErrorKind::BrokenPipe, "the compression thread has stopped")
+}
+
+
+impl<W: Write + Send + 'static> Write for ThreadedLzmaWriter<W> {
+	/// Copies `buf` for the worker to compress.  Errors from the worker are returned by the
+	/// next call after it fails.
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		if self.worker.is_none() {
+			return Err(stopped());
+		}
+
+		if self.worker.as_ref().is_some_and(JoinHandle::is_finished) {
+			return Err(self.worker_error());
+		}
+
+		let len = buf.len().min(self.chunk_size - self.chunk.len());
+		self.chunk.extend_from_slice(&buf[..len]);
+
+		if self.chunk.len() == self.chunk_size {
+			self.send_chunk()?;
+		}
+
+		Ok(len)
+	}
+
+	/// Hands the data written so far to the worker.  This doesn't wait for it to be compressed.
+	fn flush(&mut self) -> io::Result<()> {
+		if self.worker.is_none() {
+			return Err(stopped());
+		}
+
+		if !self.chunk.is_empty() {
+			self.send_chunk()?;
+		}
+
+		Ok(())
+	}
+}
+
+
+impl<W: Write + Send + 'static> Drop for ThreadedLzmaWriter<W> {
+	/// Stops the worker without finishing the stream, and waits for it so that the inner writer
+	/// is dropped before this returns.
+	fn drop(&mut self) {
+		if self.worker.is_none() {
+			return;
+		}
+
+		let unfinished = !self.failed && !thread::panicking();
+		let _ = self.join();
+
+		debug_assert!(!unfinished, "ThreadedLzmaWriter dropped without calling finish; its output is truncated");
+	}
+}
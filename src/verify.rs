@@ -0,0 +1,328 @@
+//! This module implements `verify`, which checks the integrity of `.xz` data without producing
+//! any output, like `xz -t`.
+//!
+//! Every block is decompressed into a scratch buffer so that its integrity check can be
+//! verified, and the index and footer of each stream are checked against the blocks that were
+//! actually found.
+
+use std::io::{ErrorKind, Read};
+use std::ptr;
+use lzma_sys::*;
+use error::{LzmaError, LzmaLibResult};
+use block::Block;
+use check::Check;
+use capabilities;
+use lzma_stream_wrapper::LzmaStreamWrapper;
+
+
+const DEFAULT_BUF_SIZE: usize = 4 * 1024;
+
+
+/// What `verify` found in a valid `.xz` file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifyReport {
+	compressed_size: u64,
+	uncompressed_size: u64,
+	stream_count: u64,
+	block_count: u64,
+	checks: Vec<Check>,
+}
+
+impl VerifyReport {
+	/// Size of the file, including stream padding
+	pub fn compressed_size(&self) -> u64 {
+		self.compressed_size
+	}
+
+	/// Size of the decompressed data
+	pub fn uncompressed_size(&self) -> u64 {
+		self.uncompressed_size
+	}
+
+	/// Number of streams in the file
+	pub fn stream_count(&self) -> u64 {
+		self.stream_count
+	}
+
+	/// Number of blocks in all streams
+	pub fn block_count(&self) -> u64 {
+		self.block_count
+	}
+
+	/// The integrity checks used by the streams in the file, in the order they first appear
+	pub fn checks(&self) -> &[Check] {
+		&self.checks
+	}
+}
+
+
+/// Checks that `reader` contains valid `.xz` data, reading it to the end.
+///
+/// Integrity checks, the index and the stream footer are all verified.  Concatenated streams
+/// and stream padding are supported.  Legacy `.lzma` data is rejected with `LzmaError::Format`,
+/// and data using an integrity check that liblzma can't verify with `LzmaError::Unsupported`.
+///
+/// # Examples
+///
+/// ```
+/// let compressed = lzma::compress(b"Wake up, time to die", 6).unwrap();
+/// let report = lzma::verify(&compressed[..]).unwrap();
+///
+/// assert_eq!(report.uncompressed_size(), 20);
+/// assert_eq!(report.stream_count(), 1);
+///
+/// assert!(lzma::verify(&compressed[..compressed.len() - 4]).is_err());
+/// ```
+pub fn verify<R: Read>(reader: R) -> Result<VerifyReport, LzmaError> {
+	verify_with_memlimit(reader, u64::MAX)
+}
+
+
+/// Like `verify`, but fails with `LzmaError::MemLimit` rather than use more than `memlimit`
+/// bytes of memory to decompress a block.
+///
+/// Block headers can ask for a dictionary of up to 4 GiB, so set a limit when checking data
+/// from untrusted sources.
+pub fn verify_with_memlimit<R: Read>(reader: R, memlimit: u64) -> Result<VerifyReport, LzmaError> {
+	let mut input = Input::new(reader);
+	let mut scratch = vec![0u8; DEFAULT_BUF_SIZE];
+	let mut report = VerifyReport {
+		compressed_size: 0,
+		uncompressed_size: 0,
+		stream_count: 0,
+		block_count: 0,
+		checks: Vec::new(),
+	};
+
+	// An empty file isn't an .xz file
+	if input.peek()?.is_none() {
+		return Err(LzmaError::Format);
+	}
+
+	while input.peek()?.is_some() {
+		verify_stream(&mut input, &mut scratch, memlimit, &mut report)?;
+
+		// Stream padding comes in multiples of four null bytes
+		while input.peek()? == Some(0) {
+			let mut word = [0u8; 4];
+			input.read_exact(&mut word)?;
+
+			if word != [0; 4] {
+				return Err(LzmaError::Data);
+			}
+		}
+	}
+
+	report.compressed_size = input.total;
+	Ok(report)
+}
+
+
+fn verify_stream<R: Read>(input: &mut Input<R>, scratch: &mut [u8], memlimit: u64, report: &mut VerifyReport) -> Result<(), LzmaError> {
+	let mut header = [0u8; LZMA_STREAM_HEADER_SIZE];
+	let mut header_flags = lzma_stream_flags::new();
+	input.read_exact(&mut header)?;
+
+	unsafe {
+		LzmaLibResult::from(lzma_stream_header_decode(&mut header_flags, header.as_ptr())).map(|_| ())?;
+	}
+
+	let check = Check::from_raw(header_flags.check).ok_or(LzmaError::Unsupported)?;
+	capabilities::require_check(check)?;
+	let index_hash = IndexHash::new()?;
+
+	// Blocks, up to the Index Indicator
+	loop {
+		let first = match input.peek()? {
+			Some(0) => break,
+			Some(b) => b,
+			None => return Err(LzmaError::Buf),
+		};
+
+		let mut block_header = vec![0u8; Block::header_size_from_first_byte(first)];
+		input.read_exact(&mut block_header)?;
+
+		let mut block = Block::decode_header(&block_header, check)?;
+
+		// The block decoder has no memory limit of its own
+		if block.filters().decoder_memusage()? > memlimit {
+			return Err(LzmaError::MemLimit);
+		}
+
+		let mut stream = LzmaStreamWrapper::new();
+		stream.block_decoder(block.to_raw()?)?;
+
+		loop {
+			let result = {
+				let available = input.fill()?;
+
+				if available.is_empty() {
+					return Err(LzmaError::Buf);
+				}
+
+				stream.code(available, scratch, lzma_action::LzmaRun)
+			};
+			input.consume(result.bytes_read);
+
+			if let lzma_ret::LzmaStreamEnd = result.ret? {
+				break;
+			}
+		}
+
+		if let Some(raw) = stream.block() {
+			block.update_from_raw(raw);
+		}
+
+		let unpadded_size = block.unpadded_size().ok_or(LzmaError::Data)?;
+		let uncompressed_size = block.uncompressed_size().ok_or(LzmaError::Data)?;
+
+		unsafe {
+			LzmaLibResult::from(lzma_index_hash_append(index_hash.0, unpadded_size, uncompressed_size)).map(|_| ())?;
+		}
+
+		report.uncompressed_size += uncompressed_size;
+		report.block_count += 1;
+	}
+
+	// The index must list exactly the blocks found above
+	loop {
+		let (ret, bytes_read) = {
+			let available = input.fill()?;
+
+			if available.is_empty() {
+				return Err(LzmaError::Buf);
+			}
+
+			let mut in_pos = 0;
+			let ret = unsafe {
+				LzmaLibResult::from(lzma_index_hash_decode(index_hash.0, available.as_ptr(), &mut in_pos, available.len()))
+			};
+
+			(ret, in_pos)
+		};
+		input.consume(bytes_read);
+
+		if let lzma_ret::LzmaStreamEnd = ret? {
+			break;
+		}
+	}
+
+	let mut footer = [0u8; LZMA_STREAM_HEADER_SIZE];
+	let mut footer_flags = lzma_stream_flags::new();
+	input.read_exact(&mut footer)?;
+
+	unsafe {
+		LzmaLibResult::from(lzma_stream_footer_decode(&mut footer_flags, footer.as_ptr())).map(|_| ())?;
+		LzmaLibResult::from(lzma_stream_flags_compare(&header_flags, &footer_flags)).map(|_| ())?;
+	}
+
+	if footer_flags.backward_size != unsafe { lzma_index_hash_size(index_hash.0) } {
+		return Err(LzmaError::Data);
+	}
+
+	if !report.checks.contains(&check) {
+		report.checks.push(check);
+	}
+
+	report.stream_count += 1;
+	Ok(())
+}
+
+
+/// Owns an `lzma_index_hash`, which verifies an index against the blocks that preceded it
+struct IndexHash(*mut lzma_index_hash);
+
+impl IndexHash {
+	fn new() -> Result<IndexHash, LzmaError> {
+		let index_hash = unsafe { lzma_index_hash_init(ptr::null_mut(), ptr::null()) };
+
+		if index_hash.is_null() {
+			return Err(LzmaError::Mem);
+		}
+
+		Ok(IndexHash(index_hash))
+	}
+}
+
+impl Drop for IndexHash {
+	fn drop(&mut self) {
+		unsafe {
+			lzma_index_hash_end(self.0, ptr::null());
+		}
+	}
+}
+
+
+/// Buffers `inner`, keeping count of how much of it has been consumed
+struct Input<R> {
+	inner: R,
+	buffer: Vec<u8>,
+	buffer_offset: usize,
+	buffer_len: usize,
+	total: u64,
+}
+
+impl<R: Read> Input<R> {
+	fn new(inner: R) -> Input<R> {
+		Input {
+			inner,
+			buffer: vec![0; DEFAULT_BUF_SIZE],
+			buffer_offset: 0,
+			buffer_len: 0,
+			total: 0,
+		}
+	}
+
+	/// The buffered data, reading more if there is none.  Empty at the end of the input.
+	fn fill(&mut self) -> Result<&[u8], LzmaError> {
+		while self.buffer_offset == self.buffer_len {
+			match self.inner.read(&mut self.buffer) {
+				Ok(len) => {
+					self.buffer_offset = 0;
+					self.buffer_len = len;
+
+					if len == 0 {
+						break;
+					}
+				},
+				Err(ref err) if err.kind() == ErrorKind::Interrupted => (),
+				Err(err) => return Err(err.into()),
+			}
+		}
+
+		Ok(&self.buffer[self.buffer_offset..self.buffer_len])
+	}
+
+	fn consume(&mut self, len: usize) {
+		self.buffer_offset += len;
+		self.total += len as u64;
+	}
+
+	fn peek(&mut self) -> Result<Option<u8>, LzmaError> {
+		Ok(self.fill()?.first().cloned())
+	}
+
+	/// Running out of input is reported as `LzmaError::Buf`, like liblzma does for truncated data
+	fn read_exact(&mut self, out: &mut [u8]) -> Result<(), LzmaError> {
+		let mut pos = 0;
+
+		while pos < out.len() {
+			let len = {
+				let available = self.fill()?;
+
+				if available.is_empty() {
+					return Err(LzmaError::Buf);
+				}
+
+				let len = available.len().min(out.len() - pos);
+				out[pos..(pos + len)].copy_from_slice(&available[..len]);
+				len
+			};
+
+			self.consume(len);
+			pos += len;
+		}
+
+		Ok(())
+	}
+}
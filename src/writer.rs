@@ -18,12 +18,25 @@
 //! write!(f, "It's a small world!").unwrap();
 //! f.finish().unwrap();
 //! ```
+//!
+//! `LzmaWriter::append_to` adds a new stream to the end of an existing `.xz` file, rather than
+//! recompressing it.
+//!
+//! A writer that is dropped without being finished leaves its output truncated.  Debug builds
+//! panic when that happens, unless `set_auto_finish` was used to finish on drop instead.
+//!
+//! Output the inner writer doesn't accept right away is kept until the next call, so
+//! `LzmaWriter` works with non-blocking writers: after a `WouldBlock` error, call `write`,
+//! `flush` or `try_finish` again once the inner writer is ready, and nothing is lost.
 
-use std::io::{self, Write};
+use std::io::{self, ErrorKind, IoSlice, Read, Seek, SeekFrom, Write};
+use std::thread;
 use lzma_sys::*;
-use std;
 use error::LzmaError;
-use ::Direction;
+use ::{Direction, Format};
+use options::{EncoderOptions, DecoderOptions};
+use check::Check;
+use xz::FileInfo;
 use lzma_stream_wrapper::{LzmaStreamWrapper, LzmaCodeResult};
 
 
@@ -31,10 +44,23 @@ const DEFAULT_BUF_SIZE: usize = 4 * 1024;
 
 
 pub struct LzmaWriter<T> {
-	inner: T,
+	// Only None once into_inner has taken it
+	inner: Option<T>,
 	stream: LzmaStreamWrapper,
 	buffer: Vec<u8>,
-	direction: Direction,
+	// Output in buffer that hasn't been written to inner yet
+	pending_offset: usize,
+	pending_len: usize,
+	finished: bool,
+	// Whether a decoder reached the end of the stream while writing, so that whatever is written
+	// after it is trailing data
+	ended: bool,
+	failed: bool,
+	auto_finish: bool,
+	// An error write_vectored hit after taking some data, for the next call to return
+	error: Option<io::Error>,
+	// Set where T: Write is known, so that Drop doesn't need the bound
+	on_drop: fn(&mut LzmaWriter<T>),
 }
 
 
@@ -47,24 +73,86 @@ impl<T: Write> LzmaWriter<T> {
 		LzmaWriter::with_capacity(DEFAULT_BUF_SIZE, inner, Direction::Decompress, 0)
 	}
 
+	/// Creates a compressor using the given options.  See `EncoderOptions`.
+	pub fn new_compressor_with_options(inner: T, options: &EncoderOptions) -> Result<LzmaWriter<T>, LzmaError> {
+		Ok(LzmaWriter::from_stream(DEFAULT_BUF_SIZE, inner, options.init_stream()?))
+	}
+
+	/// Creates a decompressor using the given options.  See `DecoderOptions`.
+	pub fn new_decompressor_with_options(inner: T, options: &DecoderOptions) -> Result<LzmaWriter<T>, LzmaError> {
+		Ok(LzmaWriter::from_stream(DEFAULT_BUF_SIZE, inner, options.init_stream()?))
+	}
+
 	pub fn with_capacity(capacity: usize, inner: T, direction: Direction, preset: u32) -> Result<LzmaWriter<T>, LzmaError> {
-		let mut writer = LzmaWriter {
-			inner,
-			stream: LzmaStreamWrapper::new(),
-			buffer: vec![0; capacity],
-			direction,
+		let stream = match direction {
+			Direction::Compress => EncoderOptions::new(preset).init_stream()?,
+			Direction::Decompress => DecoderOptions::new().init_stream()?,
 		};
 
-		match writer.direction {
-			Direction::Compress => {
-				writer.stream.easy_encoder(preset, lzma_check::LzmaCheckCrc64)?
-			},
-			Direction::Decompress => {
-				writer.stream.stream_decoder(std::u64::MAX, 0)?
-			},
+		Ok(LzmaWriter::from_stream(capacity, inner, stream))
+	}
+
+	/// Wraps an already initialized stream
+	pub(crate) fn from_stream(capacity: usize, inner: T, stream: LzmaStreamWrapper) -> LzmaWriter<T> {
+		LzmaWriter {
+			inner: Some(inner),
+			stream,
+			buffer: vec![0; capacity],
+			pending_offset: 0,
+			pending_len: 0,
+			finished: false,
+			ended: false,
+			failed: false,
+			auto_finish: false,
+			error: None,
+			on_drop: LzmaWriter::drop_unfinished,
+		}
+	}
+}
+
+impl<F: Read + Write + Seek> LzmaWriter<F> {
+	/// Creates a compressor that appends a new stream to the `.xz` file `file`.
+	///
+	/// The structure of the existing file is checked first, so that appending to a truncated or
+	/// corrupt file fails instead of hiding the damage; data in an invalid file returns
+	/// `LzmaError::Format` or `LzmaError::Data`.  An empty file simply gets its first stream.
+	/// Valid `.xz` files, stream padding included, are always a multiple of four bytes long,
+	/// so the new stream starts right at the end of the file without further padding.
+	///
+	/// `file` must be opened for reading and writing.  Decoding the result needs
+	/// `DecoderOptions::concatenated`, or the appended data is ignored.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use lzma::LzmaWriter;
+	/// use std::fs::OpenOptions;
+	/// use std::io::prelude::*;
+	///
+	/// let f = OpenOptions::new().read(true).write(true).open("today.log.xz").unwrap();
+	/// let mut f = LzmaWriter::append_to(f, 6).unwrap();
+	///
+	/// writeln!(f, "Another day").unwrap();
+	/// f.finish().unwrap();
+	/// ```
+	pub fn append_to(file: F, preset: u32) -> Result<LzmaWriter<F>, LzmaError> {
+		LzmaWriter::append_to_with_options(file, &EncoderOptions::new(preset))
+	}
+
+	/// Like `append_to`, using the given options.  The format must be `Format::Xz`.
+	pub fn append_to_with_options(mut file: F, options: &EncoderOptions) -> Result<LzmaWriter<F>, LzmaError> {
+		if options.output_format() != Format::Xz {
+			return Err(LzmaError::Options);
+		}
+
+		let stream = options.init_stream()?;
+
+		if file.seek(SeekFrom::End(0))? > 0 {
+			FileInfo::from_reader(&mut file)?;
+			file.seek(SeekFrom::End(0))?;
 		}
 
-		Ok(writer)
+		Ok(LzmaWriter::from_stream(DEFAULT_BUF_SIZE, file, stream))
 	}
 }
 
@@ -74,57 +162,258 @@ impl<W: Write> LzmaWriter<W> {
 	/// This *must* be called after all writing is done to ensure the last pieces of the compressed
 	/// or decompressed stream get written out.
 	pub fn finish(mut self) -> Result<W, LzmaError> {
+		self.try_finish()?;
+
+		Ok(self.into_inner())
+	}
+
+	/// Like `finish`, but without consuming the writer, so the inner writer can still be
+	/// recovered with `into_inner` if finishing fails.
+	///
+	/// If the inner writer returns `WouldBlock`, call this again later to carry on.  Calling
+	/// this again once the stream has been finished does nothing.
+	pub fn try_finish(&mut self) -> Result<(), LzmaError> {
+		if let Some(err) = self.error.take() {
+			return Err(err.into());
+		}
+
 		loop {
-			match self.lzma_code_and_write(&[], lzma_action::LzmaFinish) {
-				Ok(LzmaCodeResult {
-					ret: Ok(lzma_ret::LzmaStreamEnd),
-					bytes_read: _,
-					bytes_written: _,
-				}) => break,
-				Ok(_) => continue,
-				Err(err) => return Err(err),
+			let result = self.write_pending();
+			self.check_io(result)?;
+
+			if self.finished {
+				return Ok(());
 			}
+
+			self.code(&[], lzma_action::LzmaFinish)?;
 		}
+	}
+
+	/// Whether to finish the stream automatically when the writer is dropped (default false).
+	///
+	/// Errors that happen while finishing on drop are ignored, so call `finish` or `try_finish`
+	/// wherever they matter.
+	pub fn set_auto_finish(&mut self, auto_finish: bool) {
+		self.auto_finish = auto_finish;
+	}
+
+	pub fn get_ref(&self) -> &W {
+		self.inner.as_ref().unwrap()
+	}
+
+	/// Writing to the inner writer directly will corrupt the output.
+	pub fn get_mut(&mut self) -> &mut W {
+		self.inner.as_mut().unwrap()
+	}
+
+	/// Returns the inner writer *without* finishing the stream, so anything written to
+	/// it so far may be incomplete, and output it hasn't accepted yet is lost.  Use `finish`
+	/// to finish the stream first.
+	pub fn into_inner(mut self) -> W {
+		self.inner.take().unwrap()
+	}
+
+	/// The integrity check used by the stream being decompressed.
+	///
+	/// This is None until the stream header has been written, and always None for compressors.
+	/// Legacy `.lzma` files have no check, so they report `Check::None`.
+	pub fn check_type(&self) -> Option<Check> {
+		self.stream.check()
+	}
+
+	/// Number of bytes that followed the end of the compressed stream, not counting stream
+	/// padding.  Only counted with `TrailingData::Report`, and only complete once the writer has
+	/// been finished.
+	pub fn trailing_bytes(&self) -> u64 {
+		self.stream.trailing_bytes()
+	}
+
+	/// Whether `write_vectored` is implemented efficiently, which it is: all the slices are fed
+	/// to liblzma before returning, with small ones gathered together.
+	///
+	/// `Write::is_write_vectored` isn't stable yet, so this is provided here instead.
+	pub fn is_write_vectored(&self) -> bool {
+		true
+	}
 
-		Ok(self.inner)
+	pub(crate) fn stream(&self) -> &LzmaStreamWrapper {
+		&self.stream
 	}
 
-	#[allow(clippy::question_mark)]
-	fn lzma_code_and_write(&mut self, input: &[u8], action: lzma_action) -> Result<LzmaCodeResult, LzmaError> {
+	/// Runs liblzma on `input`, leaving its output pending.  Nothing may be pending already.
+	fn code(&mut self, input: &[u8], action: lzma_action) -> Result<LzmaCodeResult, LzmaError> {
 		let result = self.stream.code(input, &mut self.buffer, action);
-		if let Err(err) = result.ret {
-			return Err(err);
-		}
+		self.pending_offset = 0;
+		self.pending_len = result.bytes_written;
 
-		if result.bytes_written > 0 {
-			Write::write_all(&mut self.inner, &self.buffer[..result.bytes_written])?;
+		match result.ret {
+			Ok(lzma_ret::LzmaStreamEnd) => self.finished = true,
+			Ok(_) => (),
+			Err(err) => {
+				self.failed = true;
+				return Err(err);
+			},
 		}
 
 		Ok(result)
 	}
+
+	/// Writes out pending output.  On error, whatever wasn't written stays pending.
+	fn write_pending(&mut self) -> io::Result<()> {
+		while self.pending_offset < self.pending_len {
+			match self.inner.as_mut().unwrap().write(&self.buffer[self.pending_offset..self.pending_len]) {
+				Ok(0) => return Err(io::Error::new(ErrorKind::WriteZero, "failed to write the buffered data")),
+				Ok(len) => self.pending_offset += len,
+				Err(ref err) if err.kind() == ErrorKind::Interrupted => (),
+				Err(err) => return Err(err),
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Marks the writer as failed if `result` is an error that retrying won't fix
+	fn check_io(&mut self, result: io::Result<()>) -> io::Result<()> {
+		if let Err(ref err) = result {
+			if err.kind() != ErrorKind::WouldBlock {
+				self.failed = true;
+			}
+		}
+
+		result
+	}
+
+	/// Called on drop.  Finishes the stream if `set_auto_finish` asked for it.
+	fn drop_unfinished(&mut self) {
+		// Nothing to do if the inner writer was taken, the stream failed, or it ended and all
+		// of its output was written
+		if self.inner.is_none() || self.failed || (self.finished && self.pending_offset == self.pending_len) {
+			return;
+		}
+
+		if self.auto_finish {
+			let _ = self.try_finish();
+		} else {
+			debug_assert!(thread::panicking(), "LzmaWriter dropped without calling finish; its output is truncated");
+		}
+	}
 }
 
 
 impl<W: Write> Write for LzmaWriter<W> {
 	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		if let Some(err) = self.error.take() {
+			return Err(err);
+		}
+
+		// Output left over from last time has to go out before liblzma can produce more
+		let result = self.write_pending();
+		self.check_io(result)?;
+
+		if self.ended {
+			return Ok(buf.len());
+		}
+
 		// Loop until at least one byte from buf was consumed in order to be
 		// compliant with std::io::Write trait API.
 		loop {
-			match self.lzma_code_and_write(buf, lzma_action::LzmaRun) {
-				Ok(result) => if result.bytes_read == 0 && result.bytes_written > 0 {
-					continue
-				} else {
-					// If result.bytes_read is zero, then neither was something
-					// written nor read. This indicates, something went wrong.
-					return Ok(result.bytes_read)
-				},
-				Err(LzmaError::Io(err)) => return Err(err),
-				Err(err) => return Err(io::Error::new(io::ErrorKind::Other, err)),
+			let result = self.code(buf, lzma_action::LzmaRun).map_err(io::Error::other)?;
+
+			// Only a decoder reaches the end of the stream without being asked to finish.  What
+			// follows is trailing data, which TrailingData::Ignore takes and drops; the other
+			// policies never let the stream end here.
+			if self.finished {
+				self.ended = true;
+			}
+
+			let consumed = if self.ended { buf.len() } else { result.bytes_read };
+
+			let written = self.write_pending();
+			if let Err(err) = self.check_io(written) {
+				// liblzma has taken the input, so report it as written.  The output stays pending
+				// and the error comes up again on the next call.
+				if consumed > 0 {
+					return Ok(consumed);
+				}
+
+				return Err(err);
+			}
+
+			if consumed == 0 && result.bytes_written > 0 {
+				continue
+			} else {
+				// If result.bytes_read is zero, then neither was something
+				// written nor read. This indicates, something went wrong.
+				return Ok(consumed)
 			}
 		}
 	}
 
+	/// Compresses or decompresses all of the slices, rather than just the first non-empty one
+	/// like the default implementation.  Small slices are gathered into one buffer first, so
+	/// that liblzma isn't run once per slice.
+	///
+	/// If an error comes up after some data has been taken, the amount taken is returned and the
+	/// error is returned by the next call.
+	fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+		let mut gathered = [0u8; DEFAULT_BUF_SIZE];
+		let mut slices = bufs.iter().map(|buf| &buf[..]).filter(|buf| !buf.is_empty()).peekable();
+		let mut total = 0;
+
+		while let Some(first) = slices.next() {
+			let input = if first.len() >= gathered.len() {
+				first
+			} else {
+				let mut len = first.len();
+				gathered[..len].copy_from_slice(first);
+
+				while let Some(next) = slices.next_if(|next| len + next.len() <= gathered.len()) {
+					gathered[len..(len + next.len())].copy_from_slice(next);
+					len += next.len();
+				}
+
+				&gathered[..len]
+			};
+
+			let mut offset = 0;
+
+			while offset < input.len() {
+				match self.write(&input[offset..]) {
+					Ok(0) => return Ok(total + offset),
+					Ok(len) => offset += len,
+					Err(ref err) if total + offset > 0 && err.kind() == ErrorKind::WouldBlock => return Ok(total + offset),
+					Err(err) if total + offset > 0 => {
+						self.error = Some(err);
+						return Ok(total + offset);
+					},
+					Err(err) => return Err(err),
+				}
+			}
+
+			total += offset;
+		}
+
+		Ok(total)
+	}
+
+	/// Writes out output liblzma has already produced and flushes the inner writer.  This
+	/// doesn't make liblzma give up the data it is still working on; only finishing does that.
 	fn flush(&mut self) -> io::Result<()> {
-		self.inner.flush()
+		if let Some(err) = self.error.take() {
+			return Err(err);
+		}
+
+		let result = self.write_pending();
+		self.check_io(result)?;
+
+		self.get_mut().flush()
+	}
+}
+
+
+impl<W> Drop for LzmaWriter<W> {
+	fn drop(&mut self) {
+		(self.on_drop)(self);
 	}
 }
+
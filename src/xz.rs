@@ -0,0 +1,578 @@
+//! This module implements a parser for the structure of `.xz` files, written in pure Rust.
+//!
+//! An XZ file is made of one or more streams, each optionally followed by stream padding.  A
+//! stream consists of a stream header, any number of blocks, an index listing the sizes of the
+//! blocks, and a stream footer.  This module parses the headers, footers and indexes (verifying
+//! their CRC32s) into plain structs, without calling into liblzma.  It doesn't touch the
+//! compressed data itself; use `LzmaReader` or `verify` for that.
+//!
+//! `index::Index` provides similar information through liblzma.
+//!
+//!
+//! # Examples
+//!
+//! ```
+//! use lzma::xz::FileInfo;
+//!
+//! let compressed = lzma::compress(b"Enhance 224 to 176", 6).unwrap();
+//! let info = FileInfo::from_slice(&compressed).unwrap();
+//!
+//! assert_eq!(info.streams().len(), 1);
+//! assert_eq!(info.block_count(), 1);
+//! assert_eq!(info.uncompressed_size().unwrap(), 18);
+//! ```
+
+use std::io::{Read, Seek, SeekFrom};
+use error::LzmaError;
+use check::Check;
+use filter::Filter;
+
+
+/// Size of the stream header and of the stream footer
+pub const STREAM_HEADER_SIZE: usize = 12;
+
+const HEADER_MAGIC: [u8; 6] = [0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const FOOTER_MAGIC: [u8; 2] = [b'Y', b'Z'];
+
+/// A variable-length integer is at most 9 bytes long
+const VLI_BYTES_MAX: usize = 9;
+/// The largest value of a variable-length integer, which sizes may not exceed
+const VLI_MAX: u64 = u64::MAX / 2;
+/// Unpadded sizes are at least the smallest block header plus one byte of compressed data
+const UNPADDED_SIZE_MIN: u64 = 5;
+/// The total size of a block has to fit in a variable-length integer too
+const UNPADDED_SIZE_MAX: u64 = VLI_MAX & !3;
+
+
+/// The stream header, which starts every stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamHeader {
+	/// The Check ID of the integrity check used by the blocks of the stream
+	pub check_id: u8,
+}
+
+impl StreamHeader {
+	/// Parses a stream header from the first `STREAM_HEADER_SIZE` bytes of `buf`
+	pub fn parse(buf: &[u8]) -> Result<StreamHeader, LzmaError> {
+		if buf.len() < STREAM_HEADER_SIZE {
+			return Err(LzmaError::Buf);
+		}
+
+		if buf[..6] != HEADER_MAGIC {
+			return Err(LzmaError::Format);
+		}
+
+		if crc32(&buf[6..8]) != read_u32(&buf[8..12]) {
+			return Err(LzmaError::Data);
+		}
+
+		Ok(StreamHeader {
+			check_id: parse_stream_flags(&buf[6..8])?,
+		})
+	}
+
+	/// The integrity check, or None if it's one this crate doesn't know about
+	pub fn check(&self) -> Option<Check> {
+		Check::from_raw(self.check_id as u32)
+	}
+}
+
+
+/// The stream footer, which ends every stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamFooter {
+	/// The Check ID, which must match the one in the stream header
+	pub check_id: u8,
+	/// The size of the index, which sits just before the footer
+	pub backward_size: u64,
+}
+
+impl StreamFooter {
+	/// Parses a stream footer from the first `STREAM_HEADER_SIZE` bytes of `buf`
+	pub fn parse(buf: &[u8]) -> Result<StreamFooter, LzmaError> {
+		if buf.len() < STREAM_HEADER_SIZE {
+			return Err(LzmaError::Buf);
+		}
+
+		if buf[10..12] != FOOTER_MAGIC {
+			return Err(LzmaError::Format);
+		}
+
+		if crc32(&buf[4..10]) != read_u32(&buf[0..4]) {
+			return Err(LzmaError::Data);
+		}
+
+		Ok(StreamFooter {
+			check_id: parse_stream_flags(&buf[8..10])?,
+			backward_size: (read_u32(&buf[4..8]) as u64 + 1) * 4,
+		})
+	}
+}
+
+
+/// A filter as described by a block header: its Filter ID and encoded properties
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FilterFlags {
+	pub id: u64,
+	pub properties: Vec<u8>,
+}
+
+impl FilterFlags {
+	/// Decodes the properties into a `Filter`, using liblzma.
+	pub fn filter(&self) -> Result<Filter, LzmaError> {
+		Filter::from_properties(self.id, &self.properties)
+	}
+}
+
+
+/// The header at the start of every block
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockHeader {
+	/// Size of the header itself
+	pub header_size: usize,
+	/// Size of the compressed data, if recorded in the header
+	pub compressed_size: Option<u64>,
+	/// Size of the uncompressed data, if recorded in the header
+	pub uncompressed_size: Option<u64>,
+	pub filters: Vec<FilterFlags>,
+}
+
+impl BlockHeader {
+	/// Parses the block header at the start of `buf`.  Anything after the header is ignored.
+	pub fn parse(buf: &[u8]) -> Result<BlockHeader, LzmaError> {
+		let header_size = match buf.first() {
+			None => return Err(LzmaError::Buf),
+			// A zero byte is the Index Indicator, not a block header
+			Some(&0) => return Err(LzmaError::Data),
+			Some(&b) => (b as usize + 1) * 4,
+		};
+
+		if buf.len() < header_size {
+			return Err(LzmaError::Buf);
+		}
+
+		let crc_pos = header_size - 4;
+
+		if crc32(&buf[..crc_pos]) != read_u32(&buf[crc_pos..header_size]) {
+			return Err(LzmaError::Data);
+		}
+
+		let header = &buf[..crc_pos];
+		let flags = header[1];
+
+		// Reserved bits
+		if flags & 0x3c != 0 {
+			return Err(LzmaError::Options);
+		}
+
+		let mut pos = 2;
+		let compressed_size = if flags & 0x40 != 0 {
+			match decode_vli(header, &mut pos)? {
+				0 => return Err(LzmaError::Data),
+				size => Some(size),
+			}
+		} else {
+			None
+		};
+		let uncompressed_size = if flags & 0x80 != 0 {
+			Some(decode_vli(header, &mut pos)?)
+		} else {
+			None
+		};
+
+		let mut filters = Vec::new();
+
+		for _ in 0..((flags & 0x03) + 1) {
+			let id = decode_vli(header, &mut pos)?;
+			let properties_size = decode_vli(header, &mut pos)?;
+
+			if properties_size > (header.len() - pos) as u64 {
+				return Err(LzmaError::Data);
+			}
+
+			let properties = header[pos..(pos + properties_size as usize)].to_vec();
+			pos += properties_size as usize;
+
+			filters.push(FilterFlags {
+				id,
+				properties,
+			});
+		}
+
+		// The rest is Header Padding, which must be zero
+		if header[pos..].iter().any(|&b| b != 0) {
+			return Err(LzmaError::Options);
+		}
+
+		Ok(BlockHeader {
+			header_size,
+			compressed_size,
+			uncompressed_size,
+			filters,
+		})
+	}
+
+	/// Reads and parses a block header from `reader`, which must be positioned at the start of
+	/// the header.  Exactly the header is read.
+	pub fn from_reader<R: Read>(reader: &mut R) -> Result<BlockHeader, LzmaError> {
+		let mut first = [0u8; 1];
+		reader.read_exact(&mut first)?;
+
+		if first[0] == 0 {
+			return Err(LzmaError::Data);
+		}
+
+		let mut buf = vec![0u8; (first[0] as usize + 1) * 4];
+		buf[0] = first[0];
+		reader.read_exact(&mut buf[1..])?;
+
+		BlockHeader::parse(&buf)
+	}
+}
+
+
+/// An index entry describing a single block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexRecord {
+	/// Size of the block header, compressed data and check; that is, everything but the block
+	/// padding
+	pub unpadded_size: u64,
+	pub uncompressed_size: u64,
+}
+
+impl IndexRecord {
+	/// Size of the whole block, including block padding
+	pub fn total_size(&self) -> u64 {
+		round_up_4(self.unpadded_size)
+	}
+}
+
+
+/// The index of a single stream
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamIndex {
+	pub records: Vec<IndexRecord>,
+}
+
+impl StreamIndex {
+	/// Parses an index.  `buf` must hold exactly the index, which is `backward_size` bytes long.
+	pub fn parse(buf: &[u8]) -> Result<StreamIndex, LzmaError> {
+		if buf.len() < 8 || buf.len() % 4 != 0 {
+			return Err(LzmaError::Data);
+		}
+
+		let crc_pos = buf.len() - 4;
+
+		if crc32(&buf[..crc_pos]) != read_u32(&buf[crc_pos..]) {
+			return Err(LzmaError::Data);
+		}
+
+		let index = &buf[..crc_pos];
+
+		// Index Indicator
+		if index[0] != 0 {
+			return Err(LzmaError::Data);
+		}
+
+		let mut pos = 1;
+		let count = decode_vli(index, &mut pos)?;
+
+		// Each record takes at least two bytes, so a bigger count can't be right
+		if count > (index.len() / 2) as u64 {
+			return Err(LzmaError::Data);
+		}
+
+		let mut records = Vec::with_capacity(count as usize);
+
+		for _ in 0..count {
+			let unpadded_size = decode_vli(index, &mut pos)?;
+			let uncompressed_size = decode_vli(index, &mut pos)?;
+
+			if !(UNPADDED_SIZE_MIN..=UNPADDED_SIZE_MAX).contains(&unpadded_size) || uncompressed_size > VLI_MAX {
+				return Err(LzmaError::Data);
+			}
+
+			records.push(IndexRecord {
+				unpadded_size,
+				uncompressed_size,
+			});
+		}
+
+		// Index Padding fills up to the CRC32, which is four byte aligned
+		if index.len() - pos >= 4 || index[pos..].iter().any(|&b| b != 0) {
+			return Err(LzmaError::Data);
+		}
+
+		let index = StreamIndex {
+			records,
+		};
+
+		// The totals have to be representable too
+		index.blocks_size()?;
+		index.uncompressed_size()?;
+
+		Ok(index)
+	}
+
+	/// Total size of the blocks, including block padding.  Fails with `LzmaError::Data` if it
+	/// doesn't fit in a variable-length integer.
+	pub fn blocks_size(&self) -> Result<u64, LzmaError> {
+		self.records.iter().try_fold(0, |total, record| vli_add(total, record.total_size()))
+	}
+
+	/// Fails with `LzmaError::Data` if the size doesn't fit in a variable-length integer
+	pub fn uncompressed_size(&self) -> Result<u64, LzmaError> {
+		self.records.iter().try_fold(0, |total, record| vli_add(total, record.uncompressed_size))
+	}
+}
+
+
+/// Everything known about one stream of a file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamInfo {
+	/// Where the stream starts in the file
+	pub offset: u64,
+	pub header: StreamHeader,
+	pub index: StreamIndex,
+	pub footer: StreamFooter,
+	/// Size of the stream padding following the stream
+	pub padding: u64,
+}
+
+impl StreamInfo {
+	/// Size of the stream, excluding the stream padding after it
+	pub fn compressed_size(&self) -> Result<u64, LzmaError> {
+		vli_add(2 * STREAM_HEADER_SIZE as u64 + self.footer.backward_size, self.index.blocks_size()?)
+	}
+
+	pub fn uncompressed_size(&self) -> Result<u64, LzmaError> {
+		self.index.uncompressed_size()
+	}
+
+	/// The offsets in the file at which each block (and its header) starts
+	pub fn block_offsets(&self) -> Vec<u64> {
+		let mut offset = self.offset + STREAM_HEADER_SIZE as u64;
+
+		self.index.records.iter().map(|record| {
+			let block_offset = offset;
+			offset += record.total_size();
+			block_offset
+		}).collect()
+	}
+}
+
+
+/// The structure of a complete `.xz` file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileInfo {
+	streams: Vec<StreamInfo>,
+}
+
+impl FileInfo {
+	/// Parses the structure of a complete `.xz` file held in memory.
+	///
+	/// Concatenated streams and stream padding are supported.
+	pub fn from_slice(buf: &[u8]) -> Result<FileInfo, LzmaError> {
+		FileInfo::parse(buf.len() as u64, |offset, out| {
+			let offset = offset as usize;
+			out.copy_from_slice(&buf[offset..(offset + out.len())]);
+			Ok(())
+		})
+	}
+
+	/// Parses the structure of a complete `.xz` file by seeking around in `reader`.
+	///
+	/// Concatenated streams and stream padding are supported.  The position of `reader` is left
+	/// unspecified.
+	pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<FileInfo, LzmaError> {
+		let file_size = reader.seek(SeekFrom::End(0))?;
+
+		FileInfo::parse(file_size, |offset, out| {
+			reader.seek(SeekFrom::Start(offset))?;
+			reader.read_exact(out)?;
+			Ok(())
+		})
+	}
+
+	/// The streams of the file, in order
+	pub fn streams(&self) -> &[StreamInfo] {
+		&self.streams
+	}
+
+	/// Number of blocks in all streams
+	pub fn block_count(&self) -> u64 {
+		self.streams.iter().map(|stream| stream.index.records.len() as u64).sum()
+	}
+
+	/// Size of the file, including stream padding
+	pub fn file_size(&self) -> Result<u64, LzmaError> {
+		self.streams.iter().try_fold(0, |total, stream| vli_add(total, vli_add(stream.compressed_size()?, stream.padding)?))
+	}
+
+	/// Size of the decompressed data
+	pub fn uncompressed_size(&self) -> Result<u64, LzmaError> {
+		self.streams.iter().try_fold(0, |total, stream| vli_add(total, stream.uncompressed_size()?))
+	}
+
+	/// Walks backwards through the streams of a file of `file_size` bytes.
+	/// `read_at` fills its buffer with the file's contents at the given offset.
+	fn parse<F>(file_size: u64, mut read_at: F) -> Result<FileInfo, LzmaError>
+		where F: FnMut(u64, &mut [u8]) -> Result<(), LzmaError>
+	{
+		const HEADER_SIZE: u64 = STREAM_HEADER_SIZE as u64;
+
+		// XZ files are always a multiple of four bytes
+		if file_size % 4 != 0 {
+			return Err(LzmaError::Data);
+		}
+
+		let mut streams = Vec::new();
+		let mut pos = file_size;
+
+		while pos > 0 {
+			// Skip over stream padding
+			let mut padding = 0;
+			let mut word = [0u8; 4];
+
+			loop {
+				if pos < 2 * HEADER_SIZE {
+					return Err(LzmaError::Data);
+				}
+
+				read_at(pos - 4, &mut word)?;
+
+				if word != [0; 4] {
+					break;
+				}
+
+				pos -= 4;
+				padding += 4;
+			}
+
+			let mut buf = [0u8; STREAM_HEADER_SIZE];
+			read_at(pos - HEADER_SIZE, &mut buf)?;
+			let footer = StreamFooter::parse(&buf)?;
+
+			if footer.backward_size > pos - 2 * HEADER_SIZE {
+				return Err(LzmaError::Data);
+			}
+
+			let mut index_buf = vec![0u8; footer.backward_size as usize];
+			read_at(pos - HEADER_SIZE - footer.backward_size, &mut index_buf)?;
+			let index = StreamIndex::parse(&index_buf)?;
+
+			let stream_size = vli_add(2 * HEADER_SIZE + footer.backward_size, index.blocks_size()?)?;
+
+			if stream_size > pos {
+				return Err(LzmaError::Data);
+			}
+
+			let offset = pos - stream_size;
+			read_at(offset, &mut buf)?;
+			let header = StreamHeader::parse(&buf)?;
+
+			if header.check_id != footer.check_id {
+				return Err(LzmaError::Data);
+			}
+
+			streams.push(StreamInfo {
+				offset,
+				header,
+				index,
+				footer,
+				padding,
+			});
+			pos = offset;
+		}
+
+		if streams.is_empty() {
+			return Err(LzmaError::Data);
+		}
+
+		// We walked backwards
+		streams.reverse();
+
+		let info = FileInfo {
+			streams,
+		};
+
+		// Each stream's total was checked, but their sum can still be too big
+		info.uncompressed_size()?;
+
+		Ok(info)
+	}
+}
+
+
+/// Parses the Stream Flags field shared by the stream header and footer, returning the Check ID
+fn parse_stream_flags(flags: &[u8]) -> Result<u8, LzmaError> {
+	// Everything but the Check ID is reserved
+	if flags[0] != 0 || flags[1] & 0xf0 != 0 {
+		return Err(LzmaError::Options);
+	}
+
+	Ok(flags[1])
+}
+
+/// Decodes the variable-length integer at `buf[*pos]`, advancing `pos` past it
+fn decode_vli(buf: &[u8], pos: &mut usize) -> Result<u64, LzmaError> {
+	let mut value = 0;
+
+	for i in 0..VLI_BYTES_MAX {
+		let b = *buf.get(*pos).ok_or(LzmaError::Data)?;
+		*pos += 1;
+		value |= ((b & 0x7f) as u64) << (7 * i);
+
+		if b & 0x80 == 0 {
+			// Only the shortest encoding is valid
+			if b == 0 && i > 0 {
+				return Err(LzmaError::Data);
+			}
+
+			return Ok(value);
+		}
+	}
+
+	Err(LzmaError::Data)
+}
+
+/// Adds two sizes, failing if the result doesn't fit in a variable-length integer
+fn vli_add(a: u64, b: u64) -> Result<u64, LzmaError> {
+	a.checked_add(b).filter(|&sum| sum <= VLI_MAX).ok_or(LzmaError::Data)
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+	u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]])
+}
+
+fn round_up_4(n: u64) -> u64 {
+	n.div_ceil(4) * 4
+}
+
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+	let mut table = [0u32; 256];
+	let mut i = 0;
+
+	while i < 256 {
+		let mut crc = i as u32;
+		let mut bit = 0;
+
+		while bit < 8 {
+			crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+			bit += 1;
+		}
+
+		table[i] = crc;
+		i += 1;
+	}
+
+	table
+}
+
+/// The same CRC32 as xz uses (and `check::Crc32` computes through liblzma)
+fn crc32(buf: &[u8]) -> u32 {
+	!buf.iter().fold(!0u32, |crc, &b| CRC32_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8))
+}
@@ -0,0 +1,113 @@
+// End-to-end tests of the rust-xz binary.  Only built with the cli feature.
+
+extern crate lzma;
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+
+const TEST_STRING: &str = include_str!("test_file.txt");
+
+
+fn rust_xz(args: &[&str], stdin: &[u8]) -> Output {
+	let mut child = Command::new(env!("CARGO_BIN_EXE_rust-xz"))
+		.args(args)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.unwrap();
+
+	child.stdin.take().unwrap().write_all(stdin).unwrap();
+	child.wait_with_output().unwrap()
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+	let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+	dir
+}
+
+
+#[test]
+fn compress_decompress_files() {
+	let dir = temp_dir("compress_decompress_files");
+	let input = dir.join("test.txt");
+	let compressed = dir.join("test.txt.xz");
+	fs::write(&input, TEST_STRING).unwrap();
+
+	// -k keeps the input
+	let output = rust_xz(&["-k", "-9e", "--check=sha256", input.to_str().unwrap()], b"");
+	assert!(output.status.success());
+	assert!(input.exists());
+	assert_eq!(lzma::decompress(&fs::read(&compressed).unwrap()).unwrap(), TEST_STRING.as_bytes());
+
+	// Refuses to overwrite, unless forced
+	fs::write(&input, "Replaced").unwrap();
+	assert!(!rust_xz(&["-k", input.to_str().unwrap()], b"").status.success());
+	assert_eq!(lzma::decompress(&fs::read(&compressed).unwrap()).unwrap(), TEST_STRING.as_bytes());
+	assert!(rust_xz(&["-kf", input.to_str().unwrap()], b"").status.success());
+	assert_eq!(lzma::decompress(&fs::read(&compressed).unwrap()).unwrap(), b"Replaced");
+	fs::write(&input, TEST_STRING).unwrap();
+	assert!(rust_xz(&["-k", "--force", "-9e", "--check=sha256", input.to_str().unwrap()], b"").status.success());
+
+	let output = rust_xz(&["-t", compressed.to_str().unwrap()], b"");
+	assert!(output.status.success());
+
+	let output = rust_xz(&["-l", compressed.to_str().unwrap()], b"");
+	assert!(output.status.success());
+	let listing = String::from_utf8(output.stdout).unwrap();
+	assert!(listing.contains("SHA-256"));
+	assert!(listing.lines().nth(1).unwrap().trim_start().starts_with("1       1"));
+
+	// Without -k the input is removed
+	fs::remove_file(&input).unwrap();
+	let output = rust_xz(&["-d", compressed.to_str().unwrap()], b"");
+	assert!(output.status.success());
+	assert!(!compressed.exists());
+	assert_eq!(fs::read_to_string(&input).unwrap(), TEST_STRING);
+}
+
+
+#[test]
+fn stdin_stdout_formats() {
+	for format in &["xz", "lzma", "raw"] {
+		let format_arg = format!("--format={}", format);
+		let output = rust_xz(&["-c", "-3", &format_arg], TEST_STRING.as_bytes());
+		assert!(output.status.success());
+
+		let decompressed = rust_xz(&["-dc", "-3", &format_arg], &output.stdout);
+		assert!(decompressed.status.success());
+		assert_eq!(decompressed.stdout, TEST_STRING.as_bytes());
+	}
+
+	// Threads and auto-detection
+	let output = rust_xz(&["-T", "2", "-c"], TEST_STRING.as_bytes());
+	assert!(output.status.success());
+	let decompressed = rust_xz(&["-d"], &output.stdout);
+	assert_eq!(decompressed.stdout, TEST_STRING.as_bytes());
+
+	// Thread counts that don't fit in 32 bits are rejected rather than truncated
+	let output = rust_xz(&["-T", "4294967297", "-c"], b"");
+	assert!(!output.status.success());
+	assert!(String::from_utf8(output.stderr).unwrap().contains("too large"));
+
+	// A tiny memory limit makes decompression fail
+	let decompressed = rust_xz(&["-d", "--memlimit=1KiB"], &output.stdout);
+	assert!(!decompressed.status.success());
+}
+
+
+#[test]
+fn test_detects_corruption() {
+	let mut compressed = lzma::compress(TEST_STRING.as_bytes(), 6).unwrap();
+	let middle = compressed.len() / 2;
+	compressed[middle] ^= 0xff;
+
+	let output = rust_xz(&["-t"], &compressed);
+	assert!(!output.status.success());
+	assert!(!output.stderr.is_empty());
+}
@@ -1,7 +1,10 @@
+// The original tests predate these lints
+#![allow(clippy::redundant_static_lifetimes, clippy::unnecessary_mut_passed)]
+
 extern crate lzma;
 
 use lzma::error::LzmaError;
-use std::io::{Read, Cursor, Write};
+use std::io::{self, Read, Cursor, Write};
 use std::thread;
 
 
@@ -114,4 +117,1301 @@ fn test_string_1() {
 fn test_legacy_format() {
 	let decompressed = lzma::decompress(TEST_LEGACY_DATA).unwrap();
 	assert_eq!(decompressed, TEST_STRING.as_bytes());
-}
\ No newline at end of file
+}
+
+// Encode a block, record its sizes in the header, and decode it again
+#[test]
+fn block_roundtrip() {
+	use lzma::block::{Block, BlockEncoder, BlockDecoder};
+	use lzma::check::Check;
+	use lzma::filter::{Filter, FilterChain, BcjOptions, LzmaOptions};
+
+	let filters = FilterChain::from(vec![Filter::X86(BcjOptions::default()), Filter::Lzma2(LzmaOptions::preset(3).unwrap())]);
+	let block = Block::new(filters.clone(), Check::Sha256);
+	let mut encoder = BlockEncoder::new(Vec::new(), &block).unwrap();
+	encoder.write_all(TEST_STRING.as_bytes()).unwrap();
+	let (data, block) = encoder.finish().unwrap();
+
+	assert_eq!(block.uncompressed_size(), Some(TEST_STRING.len() as u64));
+	assert!(block.compressed_size().unwrap() < TEST_STRING.len() as u64);
+
+	let mut encoded = block.encode_header().unwrap();
+	let header_size = encoded.len();
+	encoded.extend_from_slice(&data);
+	assert_eq!(block.total_size(), Some(encoded.len() as u64));
+
+	let decoded = Block::decode_header(&encoded, Check::Sha256).unwrap();
+	assert_eq!(decoded.header_size().unwrap(), header_size);
+	assert_eq!(decoded.compressed_size(), block.compressed_size());
+	assert_eq!(decoded.uncompressed_size(), block.uncompressed_size());
+	assert_eq!(decoded.filters().filters().len(), 2);
+	assert_eq!(decoded.filters().filters()[0], Filter::X86(BcjOptions::default()));
+	match decoded.filters().filters()[1] {
+		Filter::Lzma2(ref options) => assert_eq!(options.dict_size, LzmaOptions::preset(3).unwrap().dict_size),
+		_ => panic!("Expected an LZMA2 filter"),
+	}
+
+	let mut decoder = BlockDecoder::new(&encoded[header_size..], &decoded).unwrap();
+	let mut s = String::new();
+	decoder.read_to_string(&mut s).unwrap();
+	assert_eq!(TEST_STRING, s);
+
+	// Sizes recorded in the header are verified
+	let mut wrong = decoded.clone();
+	wrong.set_uncompressed_size(Some(TEST_STRING.len() as u64 - 1));
+	let mut decoder = BlockDecoder::new(&encoded[header_size..], &wrong).unwrap();
+	assert!(decoder.read_to_string(&mut String::new()).is_err());
+
+	// And so are sizes given to the encoder
+	let mut encoder = BlockEncoder::new(Vec::new(), &wrong).unwrap();
+	encoder.write_all(TEST_STRING.as_bytes()).unwrap();
+	assert!(encoder.finish().is_err());
+}
+
+
+// Splitting the output into blocks, like xz --block-size and --block-list
+#[test]
+fn block_size_and_list() {
+	use lzma::index::Index;
+	use lzma::EncoderOptions;
+
+	let compress = |options: &EncoderOptions| {
+		let mut writer = lzma::LzmaWriter::new_compressor_with_options(Vec::new(), options).unwrap();
+		writer.write_all(TEST_STRING.as_bytes()).unwrap();
+		let compressed = writer.finish().unwrap();
+
+		assert_eq!(lzma::decompress(&compressed).unwrap(), TEST_STRING.as_bytes());
+		Index::from_slice(&compressed).unwrap()
+	};
+
+	let index = compress(&EncoderOptions::new(6));
+	assert_eq!(index.block_count(), 1);
+	assert_eq!(index.uncompressed_size(), TEST_STRING.len() as u64);
+
+	let block_size = 16 * 1024;
+	let index = compress(&EncoderOptions::new(6).block_size(block_size));
+	assert_eq!(index.block_count(), (TEST_STRING.len() as u64).div_ceil(block_size));
+	assert_eq!(index.stream_count(), 1);
+
+	// The last block in the list runs to the end
+	let index = compress(&EncoderOptions::new(1).block_list(&[1000, 5000]));
+	assert_eq!(index.block_count(), 3);
+
+	// Then falls back to block_size
+	let index = compress(&EncoderOptions::new(1).block_list(&[1000, 5000]).block_size(100_000));
+	assert_eq!(index.block_count(), 4);
+
+	// Unless the list ends with 0, which puts the rest into one block
+	let index = compress(&EncoderOptions::new(1).block_list(&[1000, 0]).block_size(100_000));
+	assert_eq!(index.block_count(), 2);
+
+	// 0 anywhere else is an error, like block_size(0)
+	let options = EncoderOptions::new(1).block_list(&[1000, 0, 5000]);
+	assert!(matches!(lzma::LzmaWriter::new_compressor_with_options(Vec::new(), &options), Err(LzmaError::Options)));
+
+	// LzmaReader splits blocks the same way
+	let options = EncoderOptions::new(6).block_size(block_size);
+	let mut reader = lzma::LzmaReader::new_compressor_with_options(TEST_STRING.as_bytes(), &options).unwrap();
+	let mut compressed = Vec::new();
+	reader.read_to_end(&mut compressed).unwrap();
+	assert_eq!(Index::from_slice(&compressed).unwrap().block_count(), (TEST_STRING.len() as u64).div_ceil(block_size));
+	assert_eq!(lzma::decompress(&compressed).unwrap(), TEST_STRING.as_bytes());
+}
+
+
+// Which check a stream uses, and decoding with or without verifying it
+#[test]
+fn check_type_and_verify() {
+	use lzma::check::Check;
+	use lzma::{DecoderOptions, EncoderOptions};
+
+	for &check in &[Check::None, Check::Crc32, Check::Crc64, Check::Sha256] {
+		let mut writer = lzma::LzmaWriter::new_compressor_with_options(Vec::new(), &EncoderOptions::new(6).check(check)).unwrap();
+		writer.write_all(TEST_STRING.as_bytes()).unwrap();
+		let compressed = writer.finish().unwrap();
+
+		let mut reader = lzma::LzmaReader::new_decompressor(&compressed[..]).unwrap();
+		assert_eq!(reader.check_type(), None);
+		reader.read_to_end(&mut Vec::new()).unwrap();
+		assert_eq!(reader.check_type(), Some(check));
+
+		let mut writer = lzma::LzmaWriter::new_decompressor(Vec::new()).unwrap();
+		writer.write_all(&compressed).unwrap();
+		assert_eq!(writer.check_type(), Some(check));
+	}
+
+	// Corrupt the CRC32 of the only block, which sits just before the index
+	let mut compressed = lzma::LzmaWriter::new_compressor_with_options(Vec::new(), &EncoderOptions::new(6).check(Check::Crc32)).unwrap();
+	compressed.write_all(TEST_STRING.as_bytes()).unwrap();
+	let mut compressed = compressed.finish().unwrap();
+	let len = compressed.len();
+	let backward_size = u32::from_le_bytes([compressed[len - 8], compressed[len - 7], compressed[len - 6], compressed[len - 5]]);
+	let index_size = (backward_size as usize + 1) * 4;
+	compressed[len - 12 - index_size - 1] ^= 0xff;
+
+	let mut reader = lzma::LzmaReader::new_decompressor(&compressed[..]).unwrap();
+	assert!(reader.read_to_end(&mut Vec::new()).is_err());
+
+	let mut reader = lzma::LzmaReader::new_decompressor_with_options(&compressed[..], &DecoderOptions::new().verify_check(false)).unwrap();
+	let mut s = String::new();
+	reader.read_to_string(&mut s).unwrap();
+	assert_eq!(TEST_STRING, s);
+
+	// Legacy .lzma files have no check
+	let mut reader = lzma::LzmaReader::new_decompressor(TEST_LEGACY_DATA).unwrap();
+	reader.read_to_end(&mut Vec::new()).unwrap();
+	assert_eq!(reader.check_type(), Some(Check::None));
+}
+
+
+// Standalone CRC32/CRC64 should match the checks liblzma stores in .xz files
+#[test]
+fn crc_hashers() {
+	use lzma::check::{Check, Crc32, Crc64};
+	use lzma::EncoderOptions;
+	use std::hash::Hasher;
+
+	let mut crc32 = Crc32::new();
+	Hasher::write(&mut crc32, b"12345");
+	Hasher::write(&mut crc32, b"6789");
+	assert_eq!(crc32.checksum(), 0xcbf43926);
+	assert_eq!(crc32.finish(), 0xcbf43926);
+
+	let mut crc64 = Crc64::new();
+	crc64.write_all(b"123456789").unwrap();
+	assert_eq!(crc64.checksum(), 0x995dc9bbdf1939fa);
+
+	// The check of a single-block stream sits just before the index
+	for &check in &[Check::Crc32, Check::Crc64] {
+		let mut writer = lzma::LzmaWriter::new_compressor_with_options(Vec::new(), &EncoderOptions::new(6).check(check)).unwrap();
+		writer.write_all(TEST_STRING.as_bytes()).unwrap();
+		let compressed = writer.finish().unwrap();
+		let len = compressed.len();
+		let backward_size = u32::from_le_bytes([compressed[len - 8], compressed[len - 7], compressed[len - 6], compressed[len - 5]]);
+		let check_end = len - 12 - (backward_size as usize + 1) * 4;
+		let stored = &compressed[(check_end - check.size())..check_end];
+
+		match check {
+			Check::Crc32 => {
+				let mut crc = Crc32::new();
+				crc.write_all(TEST_STRING.as_bytes()).unwrap();
+				assert_eq!(stored, &crc.checksum().to_le_bytes()[..]);
+			},
+			_ => {
+				let mut crc = Crc64::new();
+				crc.write_all(TEST_STRING.as_bytes()).unwrap();
+				assert_eq!(stored, &crc.checksum().to_le_bytes()[..]);
+			},
+		}
+	}
+}
+
+
+#[test]
+fn output_limits() {
+	use lzma::DecoderOptions;
+
+	let data = vec![0u8; 1024 * 1024];
+	let compressed = lzma::compress(&data, 6).unwrap();
+
+	// Exactly at the limit is fine
+	let options = DecoderOptions::new().max_output(data.len() as u64);
+	assert_eq!(lzma::decompress_with_options(&compressed, &options).unwrap(), data);
+
+	let options = DecoderOptions::new().max_output(data.len() as u64 - 1);
+	assert!(matches!(lzma::decompress_with_options(&compressed, &options), Err(LzmaError::OutputLimit)));
+
+	// Zeros compress far better than 100:1
+	let options = DecoderOptions::new().max_ratio(100);
+	assert!(matches!(lzma::decompress_with_options(&compressed, &options), Err(LzmaError::OutputLimit)));
+	let options = DecoderOptions::new().max_ratio(100);
+	assert_eq!(lzma::decompress_with_options(&lzma::compress(TEST_STRING.as_bytes(), 6).unwrap(), &options).unwrap(), TEST_STRING.as_bytes());
+
+	// The writer honors the limits too
+	let options = DecoderOptions::new().max_output(1000);
+	let mut writer = lzma::LzmaWriter::new_decompressor_with_options(Vec::new(), &options).unwrap();
+	let err = writer.write_all(&compressed).and_then(|_| writer.flush()).map(|_| ()).err();
+	let err = match err {
+		Some(err) => LzmaError::from(err),
+		None => writer.finish().unwrap_err(),
+	};
+	assert!(matches!(err, LzmaError::OutputLimit));
+}
+
+
+#[test]
+fn verify_reports_and_rejects() {
+	use lzma::check::Check;
+	use lzma::EncoderOptions;
+
+	let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+	let options = EncoderOptions::new(6).check(Check::Sha256).block_size(30_000);
+	let mut writer = lzma::LzmaWriter::new_compressor_with_options(Vec::new(), &options).unwrap();
+	writer.write_all(&data).unwrap();
+	let mut compressed = writer.finish().unwrap();
+
+	// A second stream, after some stream padding
+	compressed.extend_from_slice(&[0; 8]);
+	compressed.extend_from_slice(&lzma::compress(TEST_STRING.as_bytes(), 6).unwrap());
+
+	let report = lzma::verify(&compressed[..]).unwrap();
+	assert_eq!(report.compressed_size(), compressed.len() as u64);
+	assert_eq!(report.uncompressed_size(), (data.len() + TEST_STRING.len()) as u64);
+	assert_eq!(report.stream_count(), 2);
+	assert_eq!(report.block_count(), 5);
+	assert_eq!(report.checks(), &[Check::Sha256, Check::Crc64]);
+
+	// Any damage is caught
+	for &pos in &[0, 20, compressed.len() / 2, compressed.len() - 30, compressed.len() - 2] {
+		let mut corrupted = compressed.clone();
+		corrupted[pos] ^= 0x40;
+		assert!(lzma::verify(&corrupted[..]).is_err(), "corruption at {} went unnoticed", pos);
+	}
+
+	assert!(lzma::verify(&compressed[..compressed.len() - 1]).is_err());
+	assert!(lzma::verify(&[][..]).is_err());
+	assert!(lzma::verify(TEST_LEGACY_DATA).is_err());
+
+	// Check ID 2 is reserved, so liblzma can't verify it
+	let mut unknown = lzma::compress(TEST_STRING.as_bytes(), 6).unwrap();
+	unknown[7] = 2;
+	let mut crc = lzma::check::Crc32::new();
+	crc.update(&unknown[6..8]);
+	unknown[8..12].copy_from_slice(&crc.checksum().to_le_bytes());
+	assert!(matches!(lzma::verify(&unknown[..]), Err(LzmaError::Unsupported)));
+
+	// Preset 6 has an 8 MiB dictionary, which doesn't fit in 1 MiB
+	assert!(matches!(lzma::verify_with_memlimit(&compressed[..], 1024 * 1024), Err(LzmaError::MemLimit)));
+	assert_eq!(lzma::verify_with_memlimit(&compressed[..], 64 * 1024 * 1024).unwrap(), report);
+}
+
+
+#[test]
+fn capabilities() {
+	use lzma::check::Check;
+	use lzma::filter::{Filter, LzmaOptions, MatchFinder};
+
+	let capabilities = lzma::capabilities();
+	let (major, minor, patch) = capabilities.version();
+	assert!(major >= 5);
+	assert!(capabilities.version_string().starts_with(&format!("{}.{}.{}", major, minor, patch)));
+
+	// These are always built into liblzma
+	assert!(capabilities.supports_check(Check::None));
+	assert!(capabilities.supports_check(Check::Crc32));
+	assert!(capabilities.checks().contains(&Check::Crc32));
+	assert!(capabilities.supports_decoder(&Filter::Lzma2(LzmaOptions::default())));
+
+	// Everything reported as supported can actually be used
+	for &check in capabilities.checks() {
+		let options = lzma::EncoderOptions::new(1).check(check);
+		let mut writer = lzma::LzmaWriter::new_compressor_with_options(Vec::new(), &options).unwrap();
+		writer.write_all(TEST_STRING.as_bytes()).unwrap();
+		assert_eq!(lzma::decompress(&writer.finish().unwrap()).unwrap(), TEST_STRING.as_bytes());
+	}
+
+	if capabilities.supports_encoder(&Filter::Lzma2(LzmaOptions::default())) {
+		for &mf in capabilities.match_finders() {
+			let mut lzma_options = LzmaOptions::preset(1).unwrap();
+			lzma_options.mf = mf;
+			lzma_options.nice_len = if mf == MatchFinder::Hc3 || mf == MatchFinder::Bt3 { 32 } else { 64 };
+			let options = lzma::EncoderOptions::new(1).filters(vec![Filter::Lzma2(lzma_options)].into());
+			lzma::LzmaWriter::new_compressor_with_options(Vec::new(), &options).unwrap().finish().unwrap();
+		}
+	}
+}
+
+
+#[test]
+fn writer_finish_and_drop() {
+	// try_finish leaves the writer usable, and can be called more than once
+	let mut writer = lzma::LzmaWriter::new_compressor(Vec::new(), 6).unwrap();
+	writer.write_all(TEST_STRING.as_bytes()).unwrap();
+	writer.try_finish().unwrap();
+	writer.try_finish().unwrap();
+	assert!(!writer.get_ref().is_empty());
+	writer.get_mut().extend_from_slice(&lzma::compress(b"Like tears in rain", 6).unwrap());
+	let compressed = writer.finish().unwrap();
+	assert_eq!(lzma::decompress(&compressed).unwrap(), TEST_STRING.as_bytes());
+
+	// into_inner doesn't finish the stream
+	let mut writer = lzma::LzmaWriter::new_compressor(Vec::new(), 6).unwrap();
+	writer.write_all(TEST_STRING.as_bytes()).unwrap();
+	assert!(lzma::decompress(&writer.into_inner()).is_err());
+
+	// Finishing on drop, which works through types that don't require Write themselves
+	struct Holder<T> {
+		writer: lzma::LzmaWriter<T>,
+	}
+
+	let mut compressed = Vec::new();
+	{
+		let mut holder = Holder {
+			writer: lzma::LzmaWriter::new_compressor(&mut compressed, 6).unwrap(),
+		};
+		holder.writer.set_auto_finish(true);
+		holder.writer.write_all(TEST_STRING.as_bytes()).unwrap();
+	}
+	assert_eq!(lzma::decompress(&compressed).unwrap(), TEST_STRING.as_bytes());
+
+	// Dropping an unfinished writer is a bug that debug builds catch
+	if cfg!(debug_assertions) {
+		let result = thread::spawn(|| {
+			let mut writer = lzma::LzmaWriter::new_compressor(Vec::new(), 6).unwrap();
+			writer.write_all(TEST_STRING.as_bytes()).unwrap();
+		}).join();
+		assert!(result.is_err());
+	}
+}
+
+
+#[test]
+#[cfg(feature = "microlzma")]
+fn microlzma_chunks() {
+	use lzma::filter::LzmaOptions;
+	use lzma::microlzma::{self, MicroLzmaEncoder};
+
+	let data: Vec<u8> = TEST_STRING.bytes().cycle().take(200_000).enumerate().map(|(i, b)| b ^ (i % 7 == 0) as u8).collect();
+	let mut options = LzmaOptions::preset(6).unwrap();
+	options.dict_size = 64 * 1024;
+
+	// Split the data into chunks that each compress to at most 4 KiB, like EROFS does
+	let mut encoder = MicroLzmaEncoder::new(&options).unwrap();
+	let mut chunks = Vec::new();
+	let mut pos = 0;
+
+	while pos < data.len() {
+		let mut chunk = vec![0u8; 4096];
+		let (consumed, written) = encoder.encode(&data[pos..], &mut chunk).unwrap();
+		assert!(consumed > 0);
+		chunk.truncate(written);
+		chunks.push((chunk, consumed));
+		pos += consumed;
+	}
+
+	assert!(chunks.len() > 1);
+
+	let mut decompressed = Vec::new();
+	for &(ref chunk, consumed) in &chunks {
+		decompressed.extend(microlzma::decompress(chunk, consumed as u64, options.dict_size).unwrap());
+	}
+	assert_eq!(decompressed, data);
+
+	// The sizes have to be right
+	let (ref chunk, consumed) = chunks[0];
+	assert!(microlzma::decompress(chunk, consumed as u64 + 1, options.dict_size).is_err());
+	assert!(microlzma::decompress(chunk, 1 << 60, options.dict_size).is_err());
+	assert!(microlzma::decompress(&chunk[..chunk.len() - 1], consumed as u64, options.dict_size).is_err());
+	assert!(encoder.encode(&data, &mut [0u8; 5]).is_err());
+}
+
+
+#[test]
+#[cfg(feature = "filter-strings")]
+fn filter_chain_strings() {
+	use lzma::filter::{Filter, FilterChain, LzmaOptions, MatchFinder};
+
+	let chain: FilterChain = "x86 lzma2:preset=6e,dict=64MiB".parse().unwrap();
+	match chain.filters() {
+		[Filter::X86(_), Filter::Lzma2(options)] => {
+			assert_eq!(options.dict_size, 64 * 1024 * 1024);
+			assert_eq!(options.mf, MatchFinder::Bt4);
+		},
+		filters => panic!("unexpected filters {:?}", filters),
+	}
+
+	// Formatting lists every option, and parses back to the same chain
+	let s = chain.to_filter_string().unwrap();
+	assert!(s.starts_with("x86 lzma2:"), "{}", s);
+	assert!(s.contains("dict=64MiB"), "{}", s);
+	assert_eq!(s.parse::<FilterChain>().unwrap(), chain);
+
+	// A lone preset
+	assert_eq!("6".parse::<FilterChain>().unwrap(), FilterChain::preset(6).unwrap());
+	assert_eq!(FilterChain::from(vec![Filter::Lzma1(LzmaOptions::preset(1).unwrap())]).to_filter_string().unwrap().parse::<FilterChain>().unwrap().filters().len(), 1);
+
+	let err = "x86 lzma2:dict=64Mib".parse::<FilterChain>().unwrap_err();
+	assert_eq!(err.position(), 17);
+	assert!(!err.message().is_empty());
+	assert!("lzma2 x86".parse::<FilterChain>().is_err());
+	assert!("bogus".parse::<FilterChain>().unwrap_err().to_string().contains("position 0"));
+
+	// Chains liblzma can't use can't be formatted either
+	let too_long = FilterChain::from(vec![Filter::X86(Default::default()); 5]);
+	assert!(matches!(too_long.to_filter_string(), Err(LzmaError::Options)));
+	assert!(matches!(FilterChain::new().to_filter_string(), Err(LzmaError::Options)));
+}
+
+
+#[test]
+fn filter_properties() {
+	use lzma::filter::{BcjOptions, DeltaOptions, Filter, FilterChain, LzmaOptions};
+
+	// The classic 5 byte LZMA1 properties: lc/lp/pb and a little-endian dictionary size
+	let lzma1 = Filter::Lzma1(LzmaOptions::preset(6).unwrap());
+	assert_eq!(lzma1.properties().unwrap(), [0x5d, 0x00, 0x00, 0x80, 0x00]);
+
+	let decoded = Filter::from_properties(lzma1.id(), &[0x5d, 0x00, 0x00, 0x80, 0x00]).unwrap();
+	match decoded {
+		Filter::Lzma1(ref options) => {
+			assert_eq!((options.lc, options.lp, options.pb), (3, 0, 2));
+			assert_eq!(options.dict_size, 8 * 1024 * 1024);
+		},
+		_ => panic!("expected LZMA1, got {:?}", decoded),
+	}
+
+	// Raw data compressed with the decoded properties can be decompressed
+	let options = lzma::EncoderOptions::new(6).format(lzma::Format::Raw).filters(FilterChain::from(vec![lzma1.clone()]));
+	let mut writer = lzma::LzmaWriter::new_compressor_with_options(Vec::new(), &options).unwrap();
+	writer.write_all(TEST_STRING.as_bytes()).unwrap();
+	let compressed = writer.finish().unwrap();
+	let options = lzma::DecoderOptions::new().format(lzma::Format::Raw).filters(FilterChain::from(vec![decoded]));
+	let mut reader = lzma::LzmaReader::new_decompressor_with_options(&compressed[..], &options).unwrap();
+	let mut s = String::new();
+	reader.read_to_string(&mut s).unwrap();
+	assert_eq!(s, TEST_STRING);
+
+	// LZMA2 only stores the dictionary size
+	let lzma2 = Filter::Lzma2(LzmaOptions::preset(6).unwrap());
+	assert_eq!(lzma2.properties().unwrap(), [0x16]);
+	assert_eq!(lzma2.filter_flags().unwrap(), [0x21, 0x01, 0x16]);
+
+	let (filter, len) = Filter::from_filter_flags(&[0x21, 0x01, 0x16, 0xff]).unwrap();
+	assert_eq!(len, 3);
+	assert_eq!(filter.properties().unwrap(), [0x16]);
+
+	assert_eq!(Filter::Delta(DeltaOptions { distance: 4 }).properties().unwrap(), [0x03]);
+	assert_eq!(Filter::X86(BcjOptions::default()).properties().unwrap(), []);
+	assert_eq!(Filter::from_properties(0x04, &[]).unwrap(), Filter::X86(BcjOptions::default()));
+	assert!(Filter::from_properties(lzma1.id(), &[0x5d]).is_err());
+	assert!(Filter::from_filter_flags(&[0x21, 0x01]).is_err());
+
+	// Whole chains, whose Filter Flags are what block headers hold
+	let chain = FilterChain::from(vec![Filter::Delta(DeltaOptions { distance: 4 }), lzma2.clone()]);
+	assert_eq!(chain.properties().unwrap(), [(0x03, vec![0x03]), (0x21, vec![0x16])]);
+	let decoded = FilterChain::from_properties(&[(0x03, &[0x03]), (0x21, &[0x16])]).unwrap();
+	assert_eq!(decoded.properties().unwrap(), chain.properties().unwrap());
+
+	let flags = chain.filter_flags().unwrap();
+	let header = lzma::block::Block::new(chain.clone(), lzma::check::Check::Crc64).encode_header().unwrap();
+	assert_eq!(&header[2..(2 + flags.len())], &flags[..]);
+	let (decoded, len) = FilterChain::from_filter_flags(&header[2..], 2).unwrap();
+	assert_eq!(len, flags.len());
+	assert_eq!(decoded.filter_flags().unwrap(), flags);
+
+	assert!(FilterChain::new().properties().is_err());
+	assert!(FilterChain::from_properties(&[]).is_err());
+	assert!(FilterChain::from_filter_flags(&flags, 5).is_err());
+	assert!(FilterChain::from_filter_flags(&flags[..4], 2).is_err());
+}
+
+
+#[test]
+fn xz_parser_matches_liblzma() {
+	use lzma::block::Block;
+	use lzma::index::Index;
+	use lzma::xz::{BlockHeader, FileInfo, StreamIndex};
+	use lzma::EncoderOptions;
+
+	let mut files = Vec::new();
+	for &preset in &[0, 6, 9] {
+		files.push(lzma::compress(TEST_STRING.as_bytes(), preset).unwrap());
+
+		// Several blocks, with sizes recorded in the block headers by the multithreaded encoder
+		let options = EncoderOptions::new(preset).threads(2).block_size(50_000);
+		let mut writer = lzma::LzmaWriter::new_compressor_with_options(Vec::new(), &options).unwrap();
+		writer.write_all(TEST_STRING.as_bytes()).unwrap();
+		files.push(writer.finish().unwrap());
+	}
+
+	// Concatenated streams with padding
+	let mut concatenated = files[0].clone();
+	concatenated.extend_from_slice(&[0; 4]);
+	concatenated.extend_from_slice(&files[3]);
+	files.push(concatenated);
+
+	for file in &files {
+		let info = FileInfo::from_slice(file).unwrap();
+		assert_eq!(info, FileInfo::from_reader(&mut Cursor::new(&file[..])).unwrap());
+
+		let index = Index::from_slice(file).unwrap();
+		assert_eq!(info.streams().len() as u64, index.stream_count());
+		assert_eq!(info.block_count(), index.block_count());
+		assert_eq!(info.file_size().unwrap(), index.file_size());
+		assert_eq!(info.uncompressed_size().unwrap(), index.uncompressed_size());
+
+		for stream in info.streams() {
+			let check = stream.header.check().unwrap();
+			assert_eq!(index.checks(), vec![check]);
+
+			for (offset, record) in stream.block_offsets().into_iter().zip(&stream.index.records) {
+				let header = BlockHeader::parse(&file[offset as usize..]).unwrap();
+				let block = Block::decode_header(&file[offset as usize..], check).unwrap();
+
+				assert_eq!(header.header_size, block.header_size().unwrap());
+				assert_eq!(header.compressed_size, block.compressed_size());
+				assert_eq!(header.uncompressed_size, block.uncompressed_size());
+				if let Some(size) = header.uncompressed_size {
+					assert_eq!(size, record.uncompressed_size);
+				}
+
+				let filters: Vec<_> = header.filters.iter().map(|filter| filter.filter().unwrap()).collect();
+				assert_eq!(&filters[..], block.filters().filters());
+				assert_eq!(BlockHeader::from_reader(&mut &file[offset as usize..]).unwrap(), header);
+			}
+		}
+	}
+
+	// Corruption anywhere in the metadata is caught by the CRC32s
+	let file = &files[1];
+	let len = file.len();
+	for &pos in &[7, len - 10, len - 20] {
+		let mut corrupted = file.clone();
+		corrupted[pos] ^= 0x01;
+		assert!(FileInfo::from_slice(&corrupted).is_err(), "corruption at {} went unnoticed", pos);
+	}
+
+	let mut corrupted = file.clone();
+	corrupted[12 + 2] ^= 0x01;
+	assert!(BlockHeader::parse(&corrupted[12..]).is_err());
+	assert!(FileInfo::from_slice(&file[4..]).is_err());
+
+	// Sizes that overflow when added up are rejected, even with a valid CRC32
+	let index = |unpadded_sizes: &[u64]| {
+		fn vli(buf: &mut Vec<u8>, mut n: u64) {
+			while n >= 0x80 {
+				buf.push(n as u8 | 0x80);
+				n >>= 7;
+			}
+			buf.push(n as u8);
+		}
+
+		let mut buf = vec![0];
+		vli(&mut buf, unpadded_sizes.len() as u64);
+		for &size in unpadded_sizes {
+			vli(&mut buf, size);
+			vli(&mut buf, 1);
+		}
+		while buf.len() % 4 != 0 {
+			buf.push(0);
+		}
+		let mut crc = lzma::check::Crc32::new();
+		crc.update(&buf);
+		buf.extend_from_slice(&crc.checksum().to_le_bytes());
+		buf
+	};
+	let huge = (1 << 63) - 4;
+	assert_eq!(StreamIndex::parse(&index(&[huge])).unwrap().blocks_size().unwrap(), huge);
+	assert!(matches!(StreamIndex::parse(&index(&[huge, huge, huge])), Err(LzmaError::Data)));
+	assert!(matches!(StreamIndex::parse(&index(&[huge + 1])), Err(LzmaError::Data)));
+	assert!(matches!(StreamIndex::parse(&index(&[4])), Err(LzmaError::Data)));
+}
+
+
+#[test]
+fn detect_format_and_lzip() {
+	use lzma::check::Crc32;
+	use lzma::filter::{Filter, FilterChain, LzmaOptions};
+	use lzma::{DecoderOptions, EncoderOptions, Format};
+
+	let data = TEST_STRING.as_bytes();
+	let encode = |options: &EncoderOptions| {
+		let mut writer = lzma::LzmaWriter::new_compressor_with_options(Vec::new(), options).unwrap();
+		writer.write_all(data).unwrap();
+		writer.finish().unwrap()
+	};
+
+	let xz = lzma::compress(data, 6).unwrap();
+	let alone = encode(&EncoderOptions::new(6).format(Format::Lzma));
+	let raw = encode(&EncoderOptions::new(6).format(Format::Raw));
+
+	// An lzip member is a header, raw LZMA1 data with an end marker, and a trailer
+	let lzma1 = LzmaOptions::preset(6).unwrap();
+	let lzma1_data = encode(&EncoderOptions::new(6).format(Format::Raw).filters(FilterChain::from(vec![Filter::Lzma1(lzma1)])));
+	let mut crc = Crc32::new();
+	crc.update(data);
+	let mut lzip = b"LZIP\x01\x17".to_vec();
+	lzip.extend_from_slice(&lzma1_data);
+	lzip.extend_from_slice(&crc.checksum().to_le_bytes());
+	lzip.extend_from_slice(&(data.len() as u64).to_le_bytes());
+	lzip.extend_from_slice(&(lzip.len() as u64 + 8).to_le_bytes());
+
+	assert_eq!(lzma::detect_format(&xz), Some(Format::Xz));
+	assert_eq!(lzma::detect_format(&xz[..6]), Some(Format::Xz));
+	assert_eq!(lzma::detect_format(&alone), Some(Format::Lzma));
+	assert_eq!(lzma::detect_format(TEST_LEGACY_DATA), Some(Format::Lzma));
+	assert_eq!(lzma::detect_format(&raw), Some(Format::Raw));
+	assert_eq!(lzma::detect_format(&lzip), Some(Format::Lzip));
+	assert_eq!(lzma::detect_format(data), None);
+	assert_eq!(lzma::detect_format(&[]), None);
+
+	// Each one decodes with the detected format given explicitly
+	#[cfg(feature = "lzip")]
+	let formats = [&xz, &alone, &raw, &lzip];
+	#[cfg(not(feature = "lzip"))]
+	let formats = [&xz, &alone, &raw];
+
+	for compressed in &formats {
+		let options = DecoderOptions::new().format(lzma::detect_format(compressed).unwrap());
+		let mut reader = lzma::LzmaReader::new_decompressor_with_options(&compressed[..], &options).unwrap();
+		let mut output = Vec::new();
+		reader.read_to_end(&mut output).unwrap();
+		assert_eq!(output, data);
+	}
+
+	// The auto-detecting decoder handles .lz too, from liblzma 5.4 on
+	if lzma::capabilities().version() >= (5, 4, 0) {
+		assert_eq!(lzma::decompress(&lzip).unwrap(), data);
+	}
+
+	// ... but not with the wrong one
+	let options = DecoderOptions::new().format(Format::Lzip);
+
+	if cfg!(feature = "lzip") {
+		let mut reader = lzma::LzmaReader::new_decompressor_with_options(&xz[..], &options).unwrap();
+		assert!(reader.read_to_end(&mut Vec::new()).is_err());
+	} else {
+		// Without the lzip feature, .lz can't be asked for at all
+		assert!(matches!(lzma::LzmaReader::new_decompressor_with_options(&lzip[..], &options), Err(LzmaError::Unsupported)));
+	}
+
+	assert!(lzma::LzmaWriter::new_compressor_with_options(Vec::new(), &EncoderOptions::new(6).format(Format::Lzip)).is_err());
+}
+
+
+#[test]
+fn best_for_memory_budget() {
+	use lzma::{EncoderOptions, EXTREME_PRESET};
+
+	const MIB: u64 = 1024 * 1024;
+
+	// Plenty of memory gets the best there is
+	assert_eq!(EncoderOptions::best_for(u64::MAX, u64::MAX).unwrap().preset(), 9 | EXTREME_PRESET);
+
+	// Preset 6 needs about 94 MiB to compress and 9 MiB to decompress; 7 needs twice that
+	let preset = EncoderOptions::best_for(100 * MIB, 10 * MIB).unwrap().preset();
+	assert_eq!(preset & !EXTREME_PRESET, 6);
+	let preset = EncoderOptions::best_for(u64::MAX, 10 * MIB).unwrap().preset();
+	assert_eq!(preset & !EXTREME_PRESET, 6);
+
+	let options = EncoderOptions::best_for(16 * MIB, 16 * MIB).unwrap();
+	assert!(options.preset() & !EXTREME_PRESET < 6);
+	let mut writer = lzma::LzmaWriter::new_compressor_with_options(Vec::new(), &options).unwrap();
+	writer.write_all(TEST_STRING.as_bytes()).unwrap();
+	assert_eq!(lzma::decompress(&writer.finish().unwrap()).unwrap(), TEST_STRING.as_bytes());
+
+	match EncoderOptions::best_for(1024, u64::MAX) {
+		Err(LzmaError::MemLimit) => (),
+		result => panic!("expected MemLimit, got {:?}", result),
+	}
+}
+
+
+#[test]
+fn append_to_existing_file() {
+	use lzma::xz::FileInfo;
+
+	let first = b"Monday\n".repeat(100);
+	let second = b"Tuesday\n".repeat(100);
+
+	// Appending to an empty file writes a normal stream
+	let mut writer = lzma::LzmaWriter::append_to(Cursor::new(Vec::new()), 6).unwrap();
+	writer.write_all(&first).unwrap();
+	let mut file = writer.finish().unwrap();
+
+	// Stream padding before the new stream is fine
+	file.get_mut().extend_from_slice(&[0; 4]);
+
+	let mut writer = lzma::LzmaWriter::append_to(file, 6).unwrap();
+	writer.write_all(&second).unwrap();
+	let file = writer.finish().unwrap().into_inner();
+
+	let info = FileInfo::from_slice(&file).unwrap();
+	assert_eq!(info.streams().len(), 2);
+	assert_eq!(info.streams()[0].padding, 4);
+
+	let options = lzma::DecoderOptions::new().concatenated(true);
+	let decompressed = lzma::decompress_with_options(&file, &options).unwrap();
+	assert_eq!(decompressed, [&first[..], &second[..]].concat());
+
+	// Without concatenated decoding, only the first stream is read
+	assert_eq!(lzma::decompress(&file).unwrap(), first);
+
+	// Truncated and non-xz files are left alone
+	let truncated = file[..file.len() - 4].to_vec();
+	match lzma::LzmaWriter::append_to(Cursor::new(truncated), 6) {
+		Err(LzmaError::Data) | Err(LzmaError::Format) => (),
+		result => panic!("expected an error, got {:?}", result.map(|w| w.into_inner())),
+	}
+
+	let options = lzma::EncoderOptions::new(6).format(lzma::Format::Lzma);
+	let mut writer = lzma::LzmaWriter::new_compressor_with_options(Vec::new(), &options).unwrap();
+	writer.write_all(&first).unwrap();
+	let alone = writer.finish().unwrap();
+	assert!(lzma::LzmaWriter::append_to(Cursor::new(alone), 6).is_err());
+
+	match lzma::LzmaWriter::append_to_with_options(Cursor::new(Vec::new()), &options) {
+		Err(LzmaError::Options) => (),
+		result => panic!("expected Options, got {:?}", result.map(|w| w.into_inner())),
+	}
+}
+
+
+#[test]
+fn salvage_damaged_blocks() {
+	use lzma::xz::FileInfo;
+
+	const BLOCK: usize = 8 * 1024;
+
+	let data: Vec<u8> = (0..5 * BLOCK as u32).map(|i| (i * 7 / 3 % 251) as u8).collect();
+	let options = lzma::EncoderOptions::new(6).block_size(BLOCK as u64);
+	let mut writer = lzma::LzmaWriter::new_compressor_with_options(Vec::new(), &options).unwrap();
+	writer.write_all(&data).unwrap();
+	let compressed = writer.finish().unwrap();
+
+	// Intact data is recovered completely
+	let mut recovered = Vec::new();
+	let report = lzma::salvage(Cursor::new(&compressed), &mut recovered).unwrap();
+	assert!(report.is_complete());
+	assert!(report.used_index());
+	assert_eq!(report.recovered_blocks(), 5);
+	assert_eq!(recovered, data);
+
+	// Damage the middle of the third block
+	let info = FileInfo::from_slice(&compressed).unwrap();
+	let stream = &info.streams()[0];
+	let offset = stream.block_offsets()[2];
+	let mut damaged = compressed.clone();
+	damaged[offset as usize + stream.index.records[2].total_size() as usize / 2] ^= 0x55;
+
+	let expected = [&data[..2 * BLOCK], &data[3 * BLOCK..]].concat();
+	let mut recovered = Vec::new();
+	let report = lzma::salvage(Cursor::new(&damaged), &mut recovered).unwrap();
+	assert!(report.used_index());
+	assert_eq!(report.recovered_blocks(), 4);
+	assert_eq!(report.lost(), &[lzma::LostRange {
+		compressed_offset: offset,
+		compressed_size: stream.index.records[2].total_size(),
+		uncompressed_offset: 2 * BLOCK as u64,
+		uncompressed_size: Some(BLOCK as u64),
+	}]);
+	assert_eq!(recovered, expected);
+
+	// Also damage the index, so that blocks have to be found by scanning
+	let index_offset = compressed.len() - 12 - stream.footer.backward_size as usize;
+	damaged[index_offset + 2] ^= 0x55;
+
+	let mut recovered = Vec::new();
+	let report = lzma::salvage(Cursor::new(&damaged), &mut recovered).unwrap();
+	assert!(!report.used_index());
+	assert_eq!(report.recovered_blocks(), 4);
+	assert_eq!(report.lost().len(), 1);
+	assert_eq!(report.lost()[0].compressed_offset, offset);
+	assert_eq!(report.lost()[0].uncompressed_offset, 2 * BLOCK as u64);
+	assert_eq!(recovered, expected);
+
+	// Trailing garbage after a truncated file is reported too
+	let mut truncated = compressed[..stream.block_offsets()[4] as usize + 20].to_vec();
+	truncated.extend_from_slice(&[0xaa; 8]);
+	let mut recovered = Vec::new();
+	let report = lzma::salvage(Cursor::new(&truncated), &mut recovered).unwrap();
+	assert_eq!(report.recovered_blocks(), 4);
+	assert_eq!(report.lost().len(), 1);
+	assert_eq!(recovered, &data[..4 * BLOCK]);
+
+	match lzma::salvage(Cursor::new(&data), &mut Vec::new()) {
+		Err(LzmaError::Format) => (),
+		result => panic!("expected Format, got {:?}", result),
+	}
+
+	// Blocks too big to hold in memory are decompressed twice, and still only written once
+	// they've been verified
+	let big = vec![0u8; 64 * 1024 * 1024 + 1];
+	let compressed = lzma::compress(&big, 0).unwrap();
+	let mut recovered = Vec::new();
+	let report = lzma::salvage(Cursor::new(&compressed), &mut recovered).unwrap();
+	assert!(report.is_complete());
+	assert!(recovered == big);
+
+	let info = FileInfo::from_slice(&compressed).unwrap();
+	let stream = &info.streams()[0];
+	let mut damaged = compressed.clone();
+	damaged[(stream.block_offsets()[0] + stream.index.records[0].total_size()) as usize - 1] ^= 0x01;
+	let mut recovered = Vec::new();
+	let report = lzma::salvage(Cursor::new(&damaged), &mut recovered).unwrap();
+	assert_eq!(report.lost().len(), 1);
+	assert!(recovered.is_empty());
+}
+
+
+#[test]
+fn block_iterator() {
+	let data: Vec<u8> = (0..10000u32).map(|i| (i % 97) as u8).collect();
+	let options = lzma::EncoderOptions::new(6).block_size(3000);
+	let mut writer = lzma::LzmaWriter::new_compressor_with_options(Vec::new(), &options).unwrap();
+	writer.write_all(&data).unwrap();
+	let mut compressed = writer.finish().unwrap();
+
+	// A second stream continues the offsets
+	let mut writer = lzma::LzmaWriter::append_to(Cursor::new(compressed), 6).unwrap();
+	writer.write_all(b"The end").unwrap();
+	compressed = writer.finish().unwrap().into_inner();
+
+	let blocks: Vec<_> = lzma::blocks(Cursor::new(&compressed)).unwrap().map(Result::unwrap).collect();
+	let offsets: Vec<u64> = blocks.iter().map(|block| block.uncompressed_offset).collect();
+	assert_eq!(offsets, [0, 3000, 6000, 9000, 10000]);
+	assert_eq!(blocks[0].compressed_offset, 12);
+	assert_eq!(blocks[3].block.uncompressed_size(), Some(1000));
+	assert_eq!(blocks[4].block.check(), lzma::check::Check::Crc64);
+
+	let joined: Vec<u8> = blocks.iter().flat_map(|block| block.data.iter().cloned()).collect();
+	assert_eq!(joined, [&data[..], b"The end"].concat());
+
+	// Damage stops the iterator after reporting it
+	let mut damaged = compressed.clone();
+	let offset = blocks[1].compressed_offset as usize + 40;
+	damaged[offset] ^= 0x55;
+	let results: Vec<_> = lzma::blocks(Cursor::new(&damaged)).unwrap().collect();
+	assert_eq!(results.len(), 2);
+	assert!(results[0].is_ok());
+	assert!(results[1].is_err());
+
+	assert!(lzma::blocks(Cursor::new(&data)).is_err());
+}
+
+
+#[test]
+fn reader_bufread() {
+	use std::io::BufRead;
+
+	let text: String = (0..500).map(|i| format!("line {}\n", i)).collect();
+	let compressed = lzma::compress(text.as_bytes(), 6).unwrap();
+
+	let reader = lzma::LzmaReader::new_decompressor(&compressed[..]).unwrap();
+	let lines: Vec<String> = reader.lines().map(Result::unwrap).collect();
+	assert_eq!(lines.len(), 500);
+	assert_eq!(lines[499], "line 499");
+
+	// Mixing BufRead and Read doesn't lose anything
+	let mut reader = lzma::LzmaReader::new_decompressor(&compressed[..]).unwrap();
+	let mut first = String::new();
+	reader.read_line(&mut first).unwrap();
+	assert_eq!(first, "line 0\n");
+
+	let mut rest = Vec::new();
+	reader.read_to_end(&mut rest).unwrap();
+	assert_eq!([first.as_bytes(), &rest[..]].concat(), text.as_bytes());
+	assert!(reader.fill_buf().unwrap().is_empty());
+}
+
+
+#[test]
+fn vectored_io() {
+	use std::io::{IoSlice, IoSliceMut};
+
+	let header = b"HEADER ".repeat(10);
+	let body = TEST_STRING.as_bytes();
+	let slices = [IoSlice::new(&header), IoSlice::new(&[]), IoSlice::new(body)];
+
+	let mut writer = lzma::LzmaWriter::new_compressor(Vec::new(), 6).unwrap();
+	assert!(writer.is_write_vectored());
+	assert_eq!(writer.write_vectored(&slices).unwrap(), header.len() + body.len());
+	let compressed = writer.finish().unwrap();
+
+	let expected = [&header[..], body].concat();
+	assert_eq!(lzma::decompress(&compressed).unwrap(), expected);
+
+	// The first read fills both slices from what's already buffered
+	let mut reader = lzma::LzmaReader::new_decompressor(&compressed[..]).unwrap();
+	let mut first = [0u8; 20];
+	let mut second = [0u8; 30];
+	let len = reader.read_vectored(&mut [IoSliceMut::new(&mut first), IoSliceMut::new(&mut second)]).unwrap();
+	assert_eq!(len, 50);
+
+	let mut output = [&first[..], &second[..]].concat();
+	reader.read_to_end(&mut output).unwrap();
+	assert_eq!(output, expected);
+
+	// Small slices are decompressed together, so the inner writer is called far less often
+	// than when writing them one at a time
+	struct Counting(Vec<u8>, usize);
+
+	impl Write for Counting {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.1 += 1;
+			self.0.write(buf)
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	let chunks: Vec<&[u8]> = compressed.chunks(16).collect();
+	let mut writer = lzma::LzmaWriter::new_decompressor(Counting(Vec::new(), 0)).unwrap();
+	for chunk in &chunks {
+		writer.write_all(chunk).unwrap();
+	}
+	let one_at_a_time = writer.finish().unwrap();
+
+	let slices: Vec<IoSlice> = chunks.iter().map(|chunk| IoSlice::new(chunk)).collect();
+	let mut writer = lzma::LzmaWriter::new_decompressor(Counting(Vec::new(), 0)).unwrap();
+	assert_eq!(writer.write_vectored(&slices).unwrap(), compressed.len());
+	let vectored = writer.finish().unwrap();
+
+	assert_eq!(vectored.0, expected);
+	assert_eq!(one_at_a_time.0, expected);
+	assert!(vectored.1 * 4 < one_at_a_time.1, "{} inner writes, {} without gathering", vectored.1, one_at_a_time.1);
+
+	// An error after some slices were filled still returns what was read, and comes up on
+	// the next call instead.  Damaging the check makes the error come right at the end.
+	let mut damaged = lzma::compress(&header, 6).unwrap();
+	let check_pos = damaged.len() - 12 - 12 - 8;
+	damaged[check_pos] ^= 0x01;
+	let mut reader = lzma::LzmaReader::new_decompressor(&damaged[..]).unwrap();
+	let mut first = [0u8; 20];
+	let mut second = [0u8; 50];
+	let len = reader.read_vectored(&mut [IoSliceMut::new(&mut first), IoSliceMut::new(&mut second)]).unwrap();
+	assert_eq!(len, 20);
+	assert_eq!(&first[..], &header[..20]);
+	let err = reader.read(&mut second).unwrap_err();
+	assert!(matches!(err.get_ref().and_then(|err| err.downcast_ref::<LzmaError>()), Some(LzmaError::Data)));
+
+	// The same goes for writing
+	let mut damaged = lzma::compress(&expected.repeat(4), 6).unwrap();
+	let middle = damaged.len() / 2;
+	damaged[middle] ^= 0x01;
+	let mut writer = lzma::LzmaWriter::new_decompressor(Vec::new()).unwrap();
+	let len = writer.write_vectored(&[IoSlice::new(&damaged[..10]), IoSlice::new(&damaged[10..])]).unwrap();
+	assert!(len > 0 && len < damaged.len());
+	let err = writer.write(&damaged[len..]).unwrap_err();
+	assert!(matches!(err.get_ref().and_then(|err| err.downcast_ref::<LzmaError>()), Some(LzmaError::Data)));
+}
+
+
+/// Accepts or returns a few bytes at a time, and often fails with WouldBlock or Interrupted
+struct Flaky<T> {
+	inner: T,
+	calls: usize,
+}
+
+impl<T> Flaky<T> {
+	fn fail(&mut self) -> Option<io::Error> {
+		self.calls += 1;
+
+		match self.calls % 4 {
+			0 => Some(io::ErrorKind::WouldBlock.into()),
+			2 => Some(io::ErrorKind::Interrupted.into()),
+			_ => None,
+		}
+	}
+}
+
+impl<T: Write> Write for Flaky<T> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		match self.fail() {
+			Some(err) => Err(err),
+			None => self.inner.write(&buf[..buf.len().min(7)]),
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		match self.fail() {
+			Some(err) => Err(err),
+			None => self.inner.flush(),
+		}
+	}
+}
+
+impl<T: Read> Read for Flaky<T> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		match self.fail() {
+			Some(err) => Err(err),
+			None => {
+				let len = buf.len().min(5);
+				self.inner.read(&mut buf[..len])
+			},
+		}
+	}
+}
+
+fn is_retryable(err: &io::Error) -> bool {
+	err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::Interrupted
+}
+
+/// Writes all of `data` to `writer` and finishes it, retrying like an event loop would
+fn write_and_finish<W: Write>(mut writer: lzma::LzmaWriter<W>, data: &[u8]) -> W {
+	let mut input = data;
+
+	while !input.is_empty() {
+		match writer.write(input) {
+			Ok(len) => input = &input[len..],
+			Err(ref err) if is_retryable(err) => (),
+			Err(err) => panic!("{}", err),
+		}
+	}
+
+	loop {
+		match writer.flush() {
+			Ok(()) => break,
+			Err(ref err) if is_retryable(err) => (),
+			Err(err) => panic!("{}", err),
+		}
+	}
+
+	loop {
+		match writer.try_finish() {
+			Ok(()) => return writer.into_inner(),
+			Err(LzmaError::Io(ref err)) if is_retryable(err) => (),
+			Err(err) => panic!("{}", err),
+		}
+	}
+}
+
+#[test]
+fn would_block_and_interrupted() {
+	use std::io::BufRead;
+
+	// Hard to compress, so that output comes out while writing
+	let data: Vec<u8> = (0..100_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+
+	let writer = lzma::LzmaWriter::new_compressor(Flaky { inner: Vec::new(), calls: 0 }, 6).unwrap();
+	let compressed = write_and_finish(writer, &data).inner;
+	assert_eq!(lzma::decompress(&compressed).unwrap(), data);
+
+	let writer = lzma::LzmaWriter::new_decompressor(Flaky { inner: Vec::new(), calls: 0 }).unwrap();
+	assert_eq!(write_and_finish(writer, &compressed).inner, data);
+
+	let mut reader = lzma::LzmaReader::new_decompressor(Flaky { inner: &compressed[..], calls: 0 }).unwrap();
+	let mut output = Vec::new();
+	let mut buf = [0u8; 1000];
+
+	loop {
+		match reader.read(&mut buf) {
+			Ok(0) => break,
+			Ok(len) => output.extend_from_slice(&buf[..len]),
+			Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => (),
+			Err(err) => panic!("{}", err),
+		}
+	}
+
+	assert_eq!(output, data);
+
+	// The same through BufRead, where an error must not bring back consumed output
+	let mut reader = lzma::LzmaReader::new_decompressor(Flaky { inner: &compressed[..], calls: 0 }).unwrap();
+	let mut output = Vec::new();
+
+	loop {
+		let len = match reader.fill_buf() {
+			Ok(buf) => {
+				output.extend_from_slice(buf);
+				buf.len()
+			},
+			Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+			Err(err) => panic!("{}", err),
+		};
+
+		if len == 0 {
+			break;
+		}
+
+		reader.consume(len);
+	}
+
+	assert_eq!(output, data);
+}
+
+
+#[test]
+fn trailing_data_policy() {
+	use lzma::DecoderOptions;
+	use lzma::options::TrailingData;
+
+	let compressed = lzma::compress(TEST_STRING.as_bytes(), 6).unwrap();
+	let padded = [&compressed[..], &[0; 8]].concat();
+	let misaligned = [&compressed[..], &[0; 3]].concat();
+	let garbage = [&compressed[..], b"GARBAGE"].concat();
+
+	let reject = DecoderOptions::new().trailing_data(TrailingData::Reject);
+	assert_eq!(lzma::decompress_with_options(&compressed, &reject).unwrap(), TEST_STRING.as_bytes());
+	assert_eq!(lzma::decompress_with_options(&padded, &reject).unwrap(), TEST_STRING.as_bytes());
+	assert!(matches!(lzma::decompress_with_options(&misaligned, &reject), Err(LzmaError::TrailingData)));
+	assert!(matches!(lzma::decompress_with_options(&garbage, &reject), Err(LzmaError::TrailingData)));
+	assert_eq!(lzma::decompress(&garbage).unwrap(), TEST_STRING.as_bytes());
+
+	// Ignoring it is the default for the writer too, however it arrives
+	let mut writer = lzma::LzmaWriter::new_decompressor(Vec::new()).unwrap();
+	writer.write_all(&garbage).unwrap();
+	writer.write_all(b"junk").unwrap();
+	assert_eq!(writer.finish().unwrap(), TEST_STRING.as_bytes());
+
+	// The writer rejects it as soon as it sees it
+	let mut writer = lzma::LzmaWriter::new_decompressor_with_options(Vec::new(), &reject).unwrap();
+	let err = writer.write_all(&garbage).unwrap_err();
+	assert!(matches!(LzmaError::from(err), LzmaError::TrailingData));
+
+	let mut writer = lzma::LzmaWriter::new_decompressor_with_options(Vec::new(), &reject).unwrap();
+	writer.write_all(&padded).unwrap();
+	assert_eq!(writer.finish().unwrap(), TEST_STRING.as_bytes());
+
+	// Reporting counts it instead
+	let report = DecoderOptions::new().trailing_data(TrailingData::Report);
+	let mut reader = lzma::LzmaReader::new_decompressor_with_options(&garbage[..], &report).unwrap();
+	let mut output = Vec::new();
+	reader.read_to_end(&mut output).unwrap();
+	assert_eq!(output, TEST_STRING.as_bytes());
+	assert_eq!(reader.trailing_bytes(), 7);
+
+	let mut writer = lzma::LzmaWriter::new_decompressor_with_options(Vec::new(), &report).unwrap();
+	writer.write_all(&padded).unwrap();
+	writer.try_finish().unwrap();
+	assert_eq!(writer.trailing_bytes(), 0);
+	assert_eq!(writer.finish().unwrap(), TEST_STRING.as_bytes());
+}
+
+
+#[test]
+fn threaded_writer() {
+	use lzma::ThreadedLzmaWriter;
+
+	// Small chunks and a short queue, so that writes have to wait for the worker
+	let options = lzma::EncoderOptions::new(6);
+	let mut writer = ThreadedLzmaWriter::with_capacity(1000, 1, Vec::new(), &options).unwrap();
+	for line in TEST_STRING.lines() {
+		writeln!(writer, "{}", line).unwrap();
+	}
+	let compressed = writer.finish().unwrap();
+	let expected: String = TEST_STRING.lines().map(|line| format!("{}\n", line)).collect();
+	assert_eq!(lzma::decompress(&compressed).unwrap(), expected.as_bytes());
+
+	// Errors from the inner writer come back on a later write, or from finish
+	#[derive(Debug)]
+	struct Full(usize);
+
+	impl Write for Full {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			if self.0 == 0 {
+				return Err(io::Error::other("disk full"));
+			}
+
+			let len = buf.len().min(self.0);
+			self.0 -= len;
+			Ok(len)
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	// Noise doesn't compress, so the output soon runs out of room
+	let mut state = 0x2545_f491u32;
+	let data: Vec<u8> = (0..100_000).map(|_| {
+		state ^= state << 13;
+		state ^= state >> 17;
+		state ^= state << 5;
+		state as u8
+	}).collect();
+	let mut writer = ThreadedLzmaWriter::with_capacity(1000, 1, Full(5000), &options).unwrap();
+	let err = match writer.write_all(&data) {
+		Ok(()) => writer.finish().unwrap_err(),
+		Err(err) => LzmaError::Io(err),
+	};
+	assert_eq!(err.to_string(), "disk full");
+
+	// Bad options are reported straight away
+	let options = lzma::EncoderOptions::new(6).block_size(0);
+	assert!(ThreadedLzmaWriter::new_with_options(Vec::new(), &options).is_err());
+}
+
+
+#[test]
+fn parallel_compressor() {
+	use lzma::EncoderOptions;
+	use lzma::xz::FileInfo;
+
+	let data: Vec<u8> = TEST_STRING.as_bytes().iter().cycle().take(1_000_000).cloned().collect();
+	let options = EncoderOptions::new(6).threads(3).block_size(100_000).check(lzma::check::Check::Sha256);
+	let compressed = lzma::parallel::compress(&data, &options).unwrap();
+
+	// A single stream, with every block listed in the index
+	let info = FileInfo::from_slice(&compressed).unwrap();
+	assert_eq!(info.streams().len(), 1);
+	assert_eq!(info.block_count(), 10);
+	assert_eq!(info.uncompressed_size().unwrap(), data.len() as u64);
+	assert_eq!(lzma::verify(&compressed[..]).unwrap().checks(), &[lzma::check::Check::Sha256]);
+	assert_eq!(lzma::decompress(&compressed).unwrap(), data);
+
+	// Block sizes are recorded in the block headers, and a short last block is fine
+	let compressed = lzma::parallel::compress_to(&data[..250_000], Vec::new(), &options).unwrap();
+	let blocks: Vec<_> = lzma::blocks(Cursor::new(&compressed)).unwrap().map(Result::unwrap).collect();
+	assert_eq!(blocks.len(), 3);
+	assert_eq!(blocks[2].uncompressed_offset, 200_000);
+	assert_eq!(blocks[2].data, &data[200_000..250_000]);
+
+	// A read error part way through stops the workers and is returned
+	struct FailingReader;
+
+	impl Read for FailingReader {
+		fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+			Err(io::Error::other("failed"))
+		}
+	}
+
+	let failing = Cursor::new(&data[..]).chain(FailingReader);
+	assert!(matches!(lzma::parallel::compress_to(failing, Vec::new(), &options), Err(LzmaError::Io(_))));
+
+	// Empty input gives an empty stream
+	let compressed = lzma::parallel::compress(&[], &options).unwrap();
+	assert!(lzma::decompress(&compressed).unwrap().is_empty());
+
+	let options = EncoderOptions::new(6).format(lzma::Format::Lzma);
+	assert!(lzma::parallel::compress(&data, &options).is_err());
+
+	// More threads than liblzma allows is rejected up front
+	let options = EncoderOptions::new(6).threads(u32::MAX);
+	assert!(matches!(lzma::parallel::compress(&data, &options), Err(LzmaError::Options)));
+	assert!(matches!(lzma::LzmaWriter::new_compressor_with_options(Vec::new(), &options), Err(LzmaError::Options)));
+}
+
+
+#[test]
+fn cancellation_and_deadlines() {
+	use lzma::{CancellationToken, DecoderOptions, EncoderOptions};
+	use std::time::{Duration, Instant};
+
+	let compressed = lzma::compress(TEST_STRING.as_bytes(), 6).unwrap();
+
+	// Cancelling part way through a read leaves the inner reader recoverable
+	let token = CancellationToken::new();
+	let options = DecoderOptions::new().cancellation(token.clone());
+	let mut reader = lzma::LzmaReader::new_decompressor_with_options(Cursor::new(&compressed), &options).unwrap();
+	let mut buf = [0u8; 100];
+	reader.read_exact(&mut buf).unwrap();
+	assert_eq!(&buf[..], &TEST_STRING.as_bytes()[..100]);
+
+	token.cancel();
+	assert!(token.is_cancelled());
+	let err = reader.read(&mut buf).unwrap_err();
+	assert!(matches!(LzmaError::from(err), LzmaError::Cancelled));
+	assert!(reader.into_inner().position() > 0);
+
+	// The writer can be taken apart after being cancelled, without complaints on drop
+	let options = EncoderOptions::new(6).cancellation(token.clone());
+	let mut writer = lzma::LzmaWriter::new_compressor_with_options(Vec::new(), &options).unwrap();
+	let err = writer.write_all(TEST_STRING.as_bytes()).unwrap_err();
+	assert!(matches!(LzmaError::from(err), LzmaError::Cancelled));
+	assert!(matches!(writer.try_finish(), Err(LzmaError::Cancelled)));
+	assert!(writer.into_inner().is_empty());
+
+	// Deadlines work the same way, for the one-shot functions too
+	let past = Instant::now() - Duration::from_millis(1);
+	let options = EncoderOptions::new(6).deadline(past);
+	assert!(matches!(lzma::compress_with_options(TEST_STRING.as_bytes(), &options), Err(LzmaError::Cancelled)));
+	let options = DecoderOptions::new().deadline(past);
+	assert!(matches!(lzma::decompress_with_options(&compressed, &options), Err(LzmaError::Cancelled)));
+
+	let future = Instant::now() + Duration::from_secs(3600);
+	let options = DecoderOptions::new().deadline(future).cancellation(CancellationToken::new());
+	assert_eq!(lzma::decompress_with_options(&compressed, &options).unwrap(), TEST_STRING.as_bytes());
+}